@@ -7,7 +7,8 @@ fn main() {
     let logger = observer::backends::logger::Logger::builder()
         .with_path("/tmp/observer.log")
         .with_stdout()
-        .build();
+        .build()
+        .expect("Failed to open /tmp/observer.log");
     // Build Observer Object
     observer::builder(logger).init();
 
@@ -15,6 +16,7 @@ fn main() {
     let _result = observe_usage::fn_test::a();
 
     observer::end_context();
+    observer::end_app();
 
     // for x in 0..10 {
     //     // Testing with multi thread
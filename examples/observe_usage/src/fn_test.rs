@@ -1,5 +1,4 @@
 use observer::prelude::*;
-use observer::Result;
 
 
 #[observed(namespace="fn_test")]
@@ -1,49 +1,397 @@
+#[macro_use]
+extern crate serde_json;
+
+use observer::backends::batch::{Batch, Exporter};
+use std::time::Duration;
+
+static TRACE_ENDPOINT: &str = "https://trace-api.newrelic.com/trace/v1";
+static EVENT_ENDPOINT: &str =
+    "https://insights-collector.newrelic.com/v1/accounts/{account_id}/events";
+
+/// Sends every finished context to New Relic as a distributed trace (Trace API, `newrelic`
+/// format) and optionally every span as a custom event (Event API).
+///
+/// The context becomes the root span of the trace, observed functions its children; breadcrumbs
+/// are added as `field.<key>` attributes. Spans and events of many contexts are posted together
+/// from background threads, see `observer::backends::batch`. Spans and events are separate
+/// batches, so retrying one never posts the other again.
 pub struct ObserverNewRelic {
-    _segment_stack: std::sync::Arc<Vec<ackorelic::acko_segment::Segment>>,
+    config: NewRelic,
+    batch_size: Option<usize>,
+    interval: Option<Duration>,
+    spans: Option<Box<Batch<Spans>>>,
+    events: Option<Box<Batch<Events>>>,
 }
 
+#[derive(Clone)]
+struct NewRelic {
+    api_key: String,
+    service_name: String,
+    trace_endpoint: String,
+    event_endpoint: Option<String>,
+    event_type: String,
+}
+
+/// Posts spans to the Trace API.
+struct Spans(NewRelic);
+
+/// Posts events to the Event API.
+struct Events(NewRelic);
+
 impl ObserverNewRelic {
-    pub fn new() -> Self {
+    /// `api_key` is a license (ingest) key of the account the data goes to.
+    pub fn builder(api_key: &str) -> Self {
         ObserverNewRelic {
-            _segment_stack: std::sync::Arc::new(vec![]),
-        }
-    }
-}
-
-///// Implementation of Backend trait for NewRelic
-//impl observer::Backend for ObserverNewRelic {
-//    /// This will start NewRelic app
-//    fn app_started(&self) {}
-//    /// This will end NewRelic app
-//    fn app_ended(&self) {}
-//    /// This method will be called when context has been created.
-//    fn context_created(&self, id: &str) {
-//        // Need to create web transaction of NewRelic
-//        // ackorelic::newrelic_fn::nr_start_web_transaction(id);
-//        println!("ObserverNewRelic: Context Started: {}", id);
-//    }
-//    /// This method will be called when context ended.
-//    fn context_ended(&self, _ctx: &observer::Context) {
-//        // Need to end web transaction
-//        // ackorelic::newrelic_fn::nr_end_transaction()
-//        println!("ObserverNewRelic: Context Ended")
-//    }
-//    /// This method will be when span created.
-//    fn span_created(&self, id: &str) {
-//        // Need to start a newrelic segment and store it stack
-//        //        self.segment_stack
-//        //            .borrow_mut()
-//        //            .push(ackorelic::newrelic_fn::nr_start_custom_segment(id))
-//        println!("ObserverNewRelic: Span Created: {}", id)
-//    }
-//    /// This method will be when span needs to logged.
-//    fn span_data(&self, _key: &str, _value: &str) {}
-//    /// This method will be when span ended.
-//    fn span_ended(&self, _span: Option<&observer::span::Span>) {
-//        // Needs to end a segment which was stored earlier in stack
-//        //        if let Some(segment) = self.segment_stack.borrow_mut().pop() {
-//        //            ackorelic::newrelic_fn::nr_end_custom_segment(segment);
-//        //        }
-//        println!("ObserverNewRelic: Span Ended")
-//    }
-//}
+            config: NewRelic {
+                api_key: api_key.to_string(),
+                service_name: "observer".to_string(),
+                trace_endpoint: TRACE_ENDPOINT.to_string(),
+                event_endpoint: None,
+                event_type: "ObserverSpan".to_string(),
+            },
+            batch_size: None,
+            interval: None,
+            spans: None,
+            events: None,
+        }
+    }
+
+    pub fn with_service_name(mut self, name: &str) -> Self {
+        self.config.service_name = name.to_string();
+        self
+    }
+
+    /// Override the Trace API url, e.g. `https://trace-api.eu.newrelic.com/trace/v1`.
+    pub fn with_trace_endpoint(mut self, url: &str) -> Self {
+        self.config.trace_endpoint = url.to_string();
+        self
+    }
+
+    /// Also send one custom event per span to the Event API of `account_id`.
+    pub fn with_events(mut self, account_id: &str) -> Self {
+        self.config.event_endpoint = Some(EVENT_ENDPOINT.replace("{account_id}", account_id));
+        self
+    }
+
+    /// Override the Event API url, implies `with_events`.
+    pub fn with_event_endpoint(mut self, url: &str) -> Self {
+        self.config.event_endpoint = Some(url.to_string());
+        self
+    }
+
+    pub fn with_event_type(mut self, event_type: &str) -> Self {
+        self.config.event_type = event_type.to_string();
+        self
+    }
+
+    /// Most spans and events posted at once.
+    pub fn with_batch_size(mut self, items: usize) -> Self {
+        self.batch_size = Some(items);
+        self
+    }
+
+    /// Longest time spans and events wait before being posted.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    /// Fails if the sender threads can not be started.
+    pub fn build(mut self) -> observer::Result<Box<Self>> {
+        self.spans = Some(self.batch(Spans(self.config.clone()))?);
+        if self.config.event_endpoint.is_some() {
+            self.events = Some(self.batch(Events(self.config.clone()))?);
+        }
+        Ok(Box::new(self))
+    }
+
+    fn batch<E: Exporter>(&self, exporter: E) -> observer::Result<Box<Batch<E>>> {
+        let mut batch = Batch::builder(exporter);
+        if let Some(items) = self.batch_size {
+            batch = batch.with_batch_size(items);
+        }
+        if let Some(interval) = self.interval {
+            batch = batch.with_interval(interval);
+        }
+        batch.build()
+    }
+}
+
+/// Implementation of Backend trait for NewRelic
+impl observer::Backend for ObserverNewRelic {
+    fn app_started(&self) -> observer::Result<()> {
+        Ok(())
+    }
+
+    fn app_ended(&self) -> observer::Result<()> {
+        if let Some(spans) = &self.spans {
+            spans.app_ended()?;
+        }
+        if let Some(events) = &self.events {
+            events.app_ended()?;
+        }
+        Ok(())
+    }
+
+    fn context_created(&self, _id: &str) -> observer::Result<()> {
+        Ok(())
+    }
+
+    /// Hands the spans, and events, of the context to their batches.
+    fn context_ended(&self, ctx: &observer::Context) -> observer::Result<()> {
+        if let Some(spans) = &self.spans {
+            spans.context_ended(ctx)?;
+        }
+        if let Some(events) = &self.events {
+            events.context_ended(ctx)?;
+        }
+        Ok(())
+    }
+
+    fn span_created(&self, _id: &str) -> observer::Result<()> {
+        Ok(())
+    }
+
+    fn span_data(&self, _key: &str, _value: &str) -> observer::Result<()> {
+        Ok(())
+    }
+
+    fn span_ended(&self, _span: Option<&observer::span::Span>) -> observer::Result<()> {
+        Ok(())
+    }
+}
+
+impl Exporter for Spans {
+    type Item = serde_json::Value;
+
+    /// Converts the context into the spans of its trace, the context being the root span.
+    fn encode(&self, ctx: &observer::Context) -> Vec<serde_json::Value> {
+        let frame = ctx.span_stack.borrow();
+        let root = match frame.first() {
+            Some(root) => root,
+            None => return vec![],
+        };
+        let trace_id = ctx.get_key().replace('-', "");
+        let mut spans = vec![json!({
+            "id": root.get_key(),
+            "trace.id": trace_id,
+            "timestamp": root.start_time.timestamp_millis(),
+            "attributes": {
+                "name": ctx.id(),
+                "duration.ms": duration_ms(root),
+                "context.id": ctx.id(),
+            },
+        })];
+        collect_spans(ctx, &trace_id, root, &mut spans);
+        spans
+    }
+
+    fn send(&self, batch: &[serde_json::Value]) -> observer::Result<()> {
+        ureq::post(&self.0.trace_endpoint)
+            .set("Api-Key", &self.0.api_key)
+            .set("Data-Format", "newrelic")
+            .set("Data-Format-Version", "1")
+            .timeout(Duration::from_secs(10))
+            .send_json(json!([{
+                "common": {"attributes": {"service.name": self.0.service_name}},
+                "spans": batch,
+            }]))?;
+        Ok(())
+    }
+
+    fn retriable(&self, error: &failure::Error) -> bool {
+        retriable(error)
+    }
+}
+
+impl Exporter for Events {
+    type Item = serde_json::Value;
+
+    /// Converts every span of the context into an event.
+    fn encode(&self, ctx: &observer::Context) -> Vec<serde_json::Value> {
+        let frame = ctx.span_stack.borrow();
+        let mut events = vec![];
+        if let Some(root) = frame.first() {
+            collect_events(ctx, &self.0.event_type, &root.sub_frames, &mut events);
+        }
+        events
+    }
+
+    fn send(&self, batch: &[serde_json::Value]) -> observer::Result<()> {
+        if let Some(url) = &self.0.event_endpoint {
+            ureq::post(url)
+                .set("Api-Key", &self.0.api_key)
+                .timeout(Duration::from_secs(10))
+                .send_json(json!(batch))?;
+        }
+        Ok(())
+    }
+
+    fn retriable(&self, error: &failure::Error) -> bool {
+        retriable(error)
+    }
+}
+
+/// Throttled (429) and failed (5xx) requests and transport errors can pass when retried, other
+/// rejected requests are rejected again.
+fn retriable(error: &failure::Error) -> bool {
+    match error.downcast_ref::<ureq::Error>() {
+        Some(ureq::Error::Status(code, _)) => *code == 429 || *code >= 500,
+        Some(ureq::Error::Transport(_)) | None => true,
+    }
+}
+
+/// Milliseconds with their fraction.
+fn duration_ms(span: &observer::span::Span) -> f64 {
+    span.duration_micros() as f64 / 1000.0
+}
+
+/// New Relic attributes are flat scalars, nested values are sent as their JSON text.
+fn attribute(value: &serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Array(_) | serde_json::Value::Object(_) => json!(value.to_string()),
+        v => v.clone(),
+    }
+}
+
+fn collect_spans(
+    ctx: &observer::Context,
+    trace_id: &str,
+    parent: &observer::span::Span,
+    spans: &mut Vec<serde_json::Value>,
+) {
+    for span in parent.sub_frames.iter() {
+        let mut attributes = serde_json::Map::new();
+        attributes.insert("name".to_string(), json!(span.id));
+        attributes.insert("parent.id".to_string(), json!(parent.get_key()));
+        attributes.insert("duration.ms".to_string(), json!(duration_ms(span)));
+        attributes.insert("context.id".to_string(), json!(ctx.id()));
+        if let Some(success) = span.success {
+            attributes.insert("success".to_string(), json!(success));
+            attributes.insert("error".to_string(), json!(!success));
+        }
+        if let Some(err) = &span.err {
+            attributes.insert("error.message".to_string(), json!(err));
+        }
+        if let Some(result) = &span.result {
+            attributes.insert("result".to_string(), attribute(result));
+        }
+        for (key, value) in span.breadcrumbs.iter() {
+            attributes.insert(format!("field.{}", key), attribute(value));
+        }
+        spans.push(json!({
+            "id": span.get_key(),
+            "trace.id": trace_id,
+            "timestamp": span.start_time.timestamp_millis(),
+            "attributes": attributes,
+        }));
+        collect_spans(ctx, trace_id, span, spans);
+    }
+}
+
+fn collect_events(
+    ctx: &observer::Context,
+    event_type: &str,
+    spans: &[observer::span::Span],
+    events: &mut Vec<serde_json::Value>,
+) {
+    for span in spans.iter() {
+        let mut event = serde_json::Map::new();
+        event.insert("eventType".to_string(), json!(event_type));
+        event.insert(
+            "timestamp".to_string(),
+            json!(span.start_time.timestamp_millis()),
+        );
+        event.insert("spanId".to_string(), json!(span.id));
+        event.insert("contextId".to_string(), json!(ctx.id()));
+        event.insert("durationMs".to_string(), json!(duration_ms(span)));
+        if let Some(success) = span.success {
+            event.insert("success".to_string(), json!(success));
+        }
+        if let Some(err) = &span.err {
+            event.insert("error".to_string(), json!(err));
+        }
+        if let Some(result) = &span.result {
+            event.insert("result".to_string(), attribute(result));
+        }
+        for (key, value) in span.breadcrumbs.iter() {
+            event.insert(format!("field.{}", key), attribute(value));
+        }
+        events.push(serde_json::Value::Object(event));
+        collect_events(ctx, event_type, &span.sub_frames, events);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> NewRelic {
+        NewRelic {
+            api_key: "key".to_string(),
+            service_name: "observer".to_string(),
+            trace_endpoint: TRACE_ENDPOINT.to_string(),
+            event_endpoint: None,
+            event_type: "ObserverSpan".to_string(),
+        }
+    }
+
+    /// A `checkout` context with `pay` failing inside `order`.
+    fn context() -> observer::Context {
+        let ctx = observer::Context::new("checkout".to_string());
+        ctx.start_span("order");
+        ctx.start_span("pay");
+        {
+            let mut stack = ctx.span_stack.borrow_mut();
+            let pay = stack.last_mut().unwrap();
+            pay.breadcrumbs.insert("amount".to_string(), json!(7));
+            pay.breadcrumbs
+                .insert("card".to_string(), json!({"last4": "4242"}));
+        }
+        ctx.end_span(false, Some("declined".to_string()));
+        ctx.end_span(false, None);
+        ctx.finalise();
+        ctx
+    }
+
+    #[test]
+    fn spans_carry_trace_and_parent_ids() {
+        let ctx = context();
+        let spans = Spans(config()).encode(&ctx);
+        let stack = ctx.span_stack.borrow();
+        let root = &stack[0];
+        let order = &root.sub_frames[0];
+        let pay = &order.sub_frames[0];
+
+        let trace_id = ctx.get_key().replace('-', "");
+        assert_eq!(spans.len(), 3);
+        for span in spans.iter() {
+            assert_eq!(span["trace.id"], json!(trace_id));
+        }
+        assert_eq!(spans[0]["id"], json!(root.get_key()));
+        assert_eq!(spans[0]["attributes"]["name"], json!("checkout"));
+        assert!(spans[0]["attributes"].get("parent.id").is_none());
+        assert_eq!(spans[1]["id"], json!(order.get_key()));
+        assert_eq!(spans[1]["attributes"]["parent.id"], json!(root.get_key()));
+        assert_eq!(spans[2]["id"], json!(pay.get_key()));
+        assert_eq!(spans[2]["attributes"]["parent.id"], json!(order.get_key()));
+    }
+
+    #[test]
+    fn attributes_are_flattened() {
+        let spans = Spans(config()).encode(&context());
+        let pay = &spans[2]["attributes"];
+        assert_eq!(pay["name"], json!("pay"));
+        assert_eq!(pay["error"], json!(true));
+        assert_eq!(pay["error.message"], json!("declined"));
+        assert_eq!(pay["field.amount"], json!(7));
+        assert_eq!(pay["field.card"], json!(r#"{"last4":"4242"}"#));
+
+        let events = Events(config()).encode(&context());
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1]["eventType"], json!("ObserverSpan"));
+        assert_eq!(events[1]["spanId"], json!("pay"));
+        assert_eq!(events[1]["contextId"], json!("checkout"));
+        assert_eq!(events[1]["field.card"], json!(r#"{"last4":"4242"}"#));
+    }
+}
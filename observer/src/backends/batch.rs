@@ -0,0 +1,302 @@
+//! Batching for backends sending to a network service.
+//!
+//! ```ignore
+//! use observer::backends::batch::{Batch, Exporter};
+//!
+//! struct Collector;
+//!
+//! impl Exporter for Collector {
+//!     type Item = serde_json::Value;
+//!
+//!     fn encode(&self, ctx: &observer::Context) -> Vec<Self::Item> {
+//!         vec![serde_json::json!({"context": ctx.id()})]
+//!     }
+//!
+//!     fn send(&self, batch: &[Self::Item]) -> observer::Result<()> {
+//!         // post `batch` to the collector
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let backend = Batch::builder(Collector)
+//!     .with_batch_size(500)
+//!     .with_interval(std::time::Duration::from_secs(5))
+//!     .build();
+//! ```
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Encoding and transport of a network backend, `Batch` does the rest.
+pub trait Exporter: Send + Sync + 'static {
+    /// What is buffered and sent, e.g. one encoded span.
+    type Item: Send + 'static;
+
+    /// Encodes a finished context, called on the thread that ended it.
+    fn encode(&self, ctx: &crate::Context) -> Vec<Self::Item>;
+
+    /// Sends one batch, called on the background thread. Errors are retried if `retriable`.
+    ///
+    /// A batch is retried as a whole, so it should be sent in one request: a retry after a
+    /// partly accepted batch sends the accepted part again.
+    fn send(&self, batch: &[Self::Item]) -> crate::Result<()>;
+
+    /// Whether sending again can succeed, e.g. not for a request the service rejected as
+    /// invalid. Every error is retried by default.
+    fn retriable(&self, _error: &failure::Error) -> bool {
+        true
+    }
+}
+
+struct Buffer<T> {
+    items: VecDeque<T>,
+    closed: bool,
+}
+
+struct Shared<T> {
+    buffer: Mutex<Buffer<T>>,
+    ready: Condvar,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Buffer<T>> {
+        self.buffer.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Retry {
+    retries: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Retry {
+    /// Exponential backoff with "equal jitter": half the delay is fixed, half is random, so
+    /// clients failing together do not retry together.
+    fn delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_backoff);
+        let half = delay / 2;
+        let random = (uuid::Uuid::new_v4().as_u128() % 1_000_000) as u32;
+        half + half.mul_f64(f64::from(random) / 1_000_000.0)
+    }
+
+    fn send<E: Exporter>(&self, exporter: &E, batch: &[E::Item]) {
+        let mut attempt = 0;
+        loop {
+            match exporter.send(batch) {
+                Ok(()) => return,
+                Err(e) if attempt >= self.retries || !exporter.retriable(&e) => {
+                    eprintln!(
+                        "observer: dropped batch of {} after {} attempts: {}",
+                        batch.len(),
+                        attempt + 1,
+                        e
+                    );
+                    return;
+                }
+                Err(_err) => {
+                    std::thread::sleep(self.delay(attempt));
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Buffers what the `Exporter` encodes from finished contexts and sends it from a background
+/// thread, once `batch_size` items are buffered or `interval` has passed since the last send.
+///
+/// Failed sends the exporter deems `retriable` are retried with exponential backoff and jitter,
+/// batches still failing after the last retry are dropped. At most `max_buffered` items are
+/// held, contexts ending while the buffer is full are dropped and reported as errors of the
+/// backend.
+///
+/// What is buffered is sent by `app_ended`, so call `observer::end_app` before the process
+/// exits, and when the backend is dropped.
+pub struct Batch<E: Exporter> {
+    exporter: Arc<E>,
+    batch_size: usize,
+    interval: Duration,
+    max_buffered: usize,
+    retry: Retry,
+    shared: Arc<Shared<E::Item>>,
+    worker: Mutex<Option<std::thread::JoinHandle<()>>>,
+}
+
+impl<E: Exporter> Batch<E> {
+    pub fn builder(exporter: E) -> Self {
+        Batch {
+            exporter: Arc::new(exporter),
+            batch_size: 512,
+            interval: Duration::from_secs(5),
+            max_buffered: 8192,
+            retry: Retry {
+                retries: 5,
+                backoff: Duration::from_millis(100),
+                max_backoff: Duration::from_secs(30),
+            },
+            shared: Arc::new(Shared {
+                buffer: Mutex::new(Buffer {
+                    items: VecDeque::new(),
+                    closed: false,
+                }),
+                ready: Condvar::new(),
+            }),
+            worker: Mutex::new(None),
+        }
+    }
+
+    /// Most items sent at once, a send starts as soon as this many are buffered.
+    pub fn with_batch_size(mut self, items: usize) -> Self {
+        self.batch_size = items.max(1);
+        self
+    }
+
+    /// Longest time buffered items wait before being sent.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval.max(Duration::from_millis(1));
+        self
+    }
+
+    /// Most items waiting to be sent, bounding memory use when the service is slow or down.
+    pub fn with_max_buffered(mut self, items: usize) -> Self {
+        self.max_buffered = items;
+        self
+    }
+
+    /// Retries of a failed batch, waiting `backoff`, doubled each retry up to `max_backoff`.
+    pub fn with_retries(mut self, retries: u32, backoff: Duration, max_backoff: Duration) -> Self {
+        self.retry = Retry {
+            retries,
+            backoff,
+            max_backoff,
+        };
+        self
+    }
+
+    /// Fails if the sender thread can not be started.
+    pub fn build(self) -> crate::Result<Box<Self>> {
+        let exporter = self.exporter.clone();
+        let shared = self.shared.clone();
+        let (batch_size, interval, retry) = (self.batch_size, self.interval, self.retry);
+        let worker = std::thread::Builder::new()
+            .name("observer-batch".to_string())
+            .spawn(move || run(exporter.as_ref(), &shared, batch_size, interval, retry))?;
+        self.worker
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .replace(worker);
+        Ok(Box::new(self))
+    }
+
+    /// Sends everything buffered on the calling thread.
+    pub fn flush(&self) {
+        loop {
+            let batch: Vec<E::Item> = {
+                let mut buffer = self.shared.lock();
+                let n = buffer.items.len().min(self.batch_size);
+                buffer.items.drain(..n).collect()
+            };
+            if batch.is_empty() {
+                return;
+            }
+            self.retry.send(self.exporter.as_ref(), &batch);
+        }
+    }
+}
+
+fn run<E: Exporter>(
+    exporter: &E,
+    shared: &Shared<E::Item>,
+    batch_size: usize,
+    interval: Duration,
+    retry: Retry,
+) {
+    let mut last_sent = Instant::now();
+    loop {
+        let batch: Vec<E::Item> = {
+            let mut buffer = shared.lock();
+            while !buffer.closed
+                && buffer.items.len() < batch_size
+                && last_sent.elapsed() < interval
+            {
+                let wait = interval.saturating_sub(last_sent.elapsed());
+                buffer = shared
+                    .ready
+                    .wait_timeout(buffer, wait)
+                    .unwrap_or_else(|e| e.into_inner())
+                    .0;
+            }
+            if buffer.closed && buffer.items.is_empty() {
+                return;
+            }
+            let n = buffer.items.len().min(batch_size);
+            buffer.items.drain(..n).collect()
+        };
+        last_sent = Instant::now();
+        if !batch.is_empty() {
+            retry.send(exporter, &batch);
+        }
+    }
+}
+
+impl<E: Exporter> Drop for Batch<E> {
+    fn drop(&mut self) {
+        self.shared.lock().closed = true;
+        self.shared.ready.notify_all();
+        if let Some(worker) = self.worker.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl<E: Exporter> crate::Backend for Batch<E> {
+    fn app_started(&self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn app_ended(&self) -> crate::Result<()> {
+        self.flush();
+        Ok(())
+    }
+
+    fn context_created(&self, _id: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn context_ended(&self, ctx: &crate::Context) -> crate::Result<()> {
+        let items = self.exporter.encode(ctx);
+        if items.is_empty() {
+            return Ok(());
+        }
+        let mut buffer = self.shared.lock();
+        if buffer.items.len() + items.len() > self.max_buffered {
+            return Err(failure::format_err!(
+                "batch buffer full, dropped {} items of context {}",
+                items.len(),
+                ctx.id()
+            ));
+        }
+        buffer.items.extend(items);
+        if buffer.items.len() >= self.batch_size {
+            self.shared.ready.notify_one();
+        }
+        Ok(())
+    }
+
+    fn span_created(&self, _id: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn span_data(&self, _key: &str, _value: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn span_ended(&self, _span: Option<&crate::span::Span>) -> crate::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+static NEXT_TID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static TID: u64 = NEXT_TID.fetch_add(1, Ordering::Relaxed);
+}
+
+enum Output {
+    /// One `<context id>-<context key>.json` file per context in the directory.
+    Directory(String),
+    /// Events appended to one file in the JSON array format, rolled to `<path>.1` once it grows
+    /// past `max_bytes`.
+    Rolling { path: String, max_bytes: u64 },
+}
+
+/// Writes finished contexts in the Trace Event Format, to be opened in `chrome://tracing` or
+/// Perfetto.
+pub struct ChromeTrace {
+    output: Output,
+    lock: Mutex<()>,
+}
+
+impl ChromeTrace {
+    pub fn builder() -> Self {
+        ChromeTrace {
+            output: Output::Directory(std::env::temp_dir().to_string_lossy().to_string()),
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn with_directory(mut self, dir: &str) -> Self {
+        self.output = Output::Directory(dir.to_string());
+        self
+    }
+
+    pub fn with_rolling_file(mut self, path: &str, max_bytes: u64) -> Self {
+        self.output = Output::Rolling {
+            path: path.to_string(),
+            max_bytes,
+        };
+        self
+    }
+
+    pub fn build(self) -> Box<Self> {
+        if let Output::Directory(dir) = &self.output {
+            std::fs::create_dir_all(dir).expect("Failed to create trace directory");
+        }
+        Box::new(self)
+    }
+
+    fn write(&self, ctx: &crate::Context) -> std::io::Result<()> {
+        let _lock = self.lock.lock();
+        match &self.output {
+            Output::Directory(dir) => {
+                let name: String = format!("{}-{}.json", ctx.id(), ctx.get_key())
+                    .chars()
+                    .map(|c| if c == '/' || c == '\\' { '_' } else { c })
+                    .collect();
+                let path = std::path::Path::new(dir).join(name);
+                std::fs::write(path, trace(ctx).to_string())
+            }
+            Output::Rolling { path, max_bytes } => {
+                let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+                if size > *max_bytes {
+                    std::fs::rename(path, format!("{}.1", path))?;
+                }
+                let mut file = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)?;
+                let mut events = if size == 0 || size > *max_bytes {
+                    "[\n".to_string()
+                } else {
+                    "".to_string()
+                };
+                for event in trace_events(ctx).iter() {
+                    events.push_str(&event.to_string());
+                    events.push_str(",\n");
+                }
+                file.write_all(events.as_bytes())
+            }
+        }
+    }
+}
+
+impl crate::Backend for ChromeTrace {
+    fn app_started(&self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn app_ended(&self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn context_created(&self, _id: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn context_ended(&self, ctx: &crate::Context) -> crate::Result<()> {
+        self.write(ctx)
+            .map_err(|e| failure::format_err!("failed to write chrome trace: {}", e))
+    }
+
+    fn span_created(&self, _id: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn span_data(&self, _key: &str, _value: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn span_ended(&self, _span: Option<&crate::span::Span>) -> crate::Result<()> {
+        Ok(())
+    }
+}
+
+/// The context as a Trace Event Format JSON object (`{"traceEvents": [...]}`).
+pub fn trace(ctx: &crate::Context) -> serde_json::Value {
+    json!({
+        "traceEvents": trace_events(ctx),
+        "displayTimeUnit": "ms",
+    })
+}
+
+/// Complete (`X`) events for the context and every span, with breadcrumbs, result and error as
+/// args, and an instant (`i`) event per span log.
+///
+/// Contexts are thread local, so events get the id of the calling thread.
+pub fn trace_events(ctx: &crate::Context) -> Vec<serde_json::Value> {
+    let pid = std::process::id();
+    let tid = TID.with(|tid| *tid);
+    let mut events = vec![json!({
+        "name": "thread_name",
+        "ph": "M",
+        "pid": pid,
+        "tid": tid,
+        "args": {"name": std::thread::current().name().unwrap_or("unnamed")},
+    })];
+    let frame = ctx.span_stack.borrow();
+    if let Some(frame) = frame.first() {
+        events.push(json!({
+            "name": ctx.id(),
+            "cat": "context",
+            "ph": "X",
+            "ts": micros(&frame.start_time),
+            "dur": frame.duration_micros(),
+            "pid": pid,
+            "tid": tid,
+            "args": {"context_id": ctx.id()},
+        }));
+        span_events(&mut events, &frame.sub_frames, pid, tid);
+    }
+    events
+}
+
+fn span_events(
+    events: &mut Vec<serde_json::Value>,
+    spans: &[crate::span::Span],
+    pid: u32,
+    tid: u64,
+) {
+    for span in spans.iter() {
+        let mut args = serde_json::Map::new();
+        for (key, value) in span.breadcrumbs.iter() {
+            args.insert(key.clone(), value.clone());
+        }
+        if let Some(success) = span.success {
+            args.insert("@@success".to_string(), json!(success));
+        }
+        if let Some(result) = &span.result {
+            args.insert("#result".to_string(), result.clone());
+        }
+        if let Some(err) = &span.err {
+            args.insert("#error".to_string(), json!(err));
+        }
+        events.push(json!({
+            "name": span.id,
+            "cat": "span",
+            "ph": "X",
+            "ts": micros(&span.start_time),
+            "dur": span.duration_micros(),
+            "pid": pid,
+            "tid": tid,
+            "args": args,
+        }));
+        for (at, log) in span.logs.iter() {
+            events.push(json!({
+                "name": log,
+                "cat": "log",
+                "ph": "i",
+                "s": "t",
+                "ts": micros(at),
+                "pid": pid,
+                "tid": tid,
+            }));
+        }
+        span_events(events, &span.sub_frames, pid, tid);
+    }
+}
+
+fn micros(time: &chrono::DateTime<chrono::Utc>) -> i64 {
+    time.timestamp() * 1_000_000 + i64::from(time.timestamp_subsec_micros())
+}
@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Aggregates self-time of spans by their stack of span ids (`main;foo__create;db__select`)
+/// over all finished contexts.
+///
+/// Every `interval` the aggregate is written as folded stacks, the input format of
+/// `flamegraph.pl` and `inferno`, to `<directory>/observer-<timestamp>.folded` and reset, so
+/// each file covers one interval. Counts are microseconds. What was aggregated since the last
+/// write is written by `observer::end_app`, which also stops the writer thread.
+pub struct Flamegraph {
+    directory: String,
+    interval: Duration,
+    stacks: FoldedStacks,
+    writer: Mutex<Option<Writer>>,
+}
+
+/// The thread writing every interval, it stops when `stop` is dropped.
+struct Writer {
+    stop: Sender<()>,
+    thread: std::thread::JoinHandle<()>,
+}
+
+#[derive(Clone)]
+pub struct FoldedStacks {
+    inner: Arc<Mutex<BTreeMap<String, i64>>>,
+}
+
+impl Flamegraph {
+    pub fn builder(directory: &str) -> Self {
+        Flamegraph {
+            directory: directory.to_string(),
+            interval: Duration::from_secs(60),
+            stacks: FoldedStacks {
+                inner: Arc::new(Mutex::new(BTreeMap::new())),
+            },
+            writer: Mutex::new(None),
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn build(self) -> Box<Self> {
+        std::fs::create_dir_all(&self.directory).expect("Failed to create flamegraph directory");
+        Box::new(self)
+    }
+
+    /// Handle to the aggregate, usable after the backend is moved into the observer.
+    pub fn stacks(&self) -> FoldedStacks {
+        self.stacks.clone()
+    }
+
+    fn stop_writer(&self) {
+        let writer = self.writer.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(Writer { stop, thread }) = writer {
+            drop(stop);
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Flamegraph {
+    fn drop(&mut self) {
+        self.stop_writer();
+    }
+}
+
+impl FoldedStacks {
+    /// Folded stacks aggregated since the last dump, one `stack count` line each, and resets.
+    pub fn dump(&self) -> String {
+        let stacks = match self.inner.lock() {
+            Ok(mut stacks) => std::mem::take(&mut *stacks),
+            Err(_err) => return "".to_string(),
+        };
+        let mut writer = "".to_string();
+        for (stack, micros) in stacks.iter() {
+            writer.push_str(&format!("{} {}\n", stack, micros));
+        }
+        writer
+    }
+
+    fn write(&self, directory: &str) {
+        let folded = self.dump();
+        if folded.is_empty() {
+            return;
+        }
+        let path = std::path::Path::new(directory).join(format!(
+            "observer-{}.folded",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S%.3f")
+        ));
+        if let Err(e) = std::fs::write(&path, folded) {
+            eprintln!("observer: failed to write {}: {}", path.display(), e);
+        }
+    }
+
+    fn add(&self, stack: &str, spans: &[crate::span::Span]) {
+        if let Ok(mut stacks) = self.inner.lock() {
+            add_spans(&mut stacks, stack, spans);
+        }
+    }
+}
+
+impl crate::Backend for Flamegraph {
+    fn app_started(&self) -> crate::Result<()> {
+        // a backend started again keeps a single writer
+        self.stop_writer();
+        let stacks = self.stacks();
+        let directory = self.directory.clone();
+        let interval = self.interval;
+        let (stop, stopped) = channel::<()>();
+        let thread = std::thread::Builder::new()
+            .name("observer-flamegraph".to_string())
+            .spawn(move || loop {
+                match stopped.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => stacks.write(&directory),
+                    _ => return,
+                }
+            })?;
+        self.writer
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .replace(Writer { stop, thread });
+        Ok(())
+    }
+
+    fn app_ended(&self) -> crate::Result<()> {
+        self.stop_writer();
+        self.stacks.write(&self.directory);
+        Ok(())
+    }
+
+    fn context_created(&self, _id: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn context_ended(&self, ctx: &crate::Context) -> crate::Result<()> {
+        let frame = ctx.span_stack.borrow();
+        if let Some(frame) = frame.first() {
+            self.stacks.add("", std::slice::from_ref(frame));
+        }
+        Ok(())
+    }
+
+    fn span_created(&self, _id: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn span_data(&self, _key: &str, _value: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn span_ended(&self, _span: Option<&crate::span::Span>) -> crate::Result<()> {
+        Ok(())
+    }
+}
+
+fn add_spans(stacks: &mut BTreeMap<String, i64>, parent: &str, spans: &[crate::span::Span]) {
+    for span in spans.iter() {
+        let frame: String = span
+            .id
+            .chars()
+            .map(|c| {
+                if c == ';' || c.is_whitespace() {
+                    '_'
+                } else {
+                    c
+                }
+            })
+            .collect();
+        let stack = if parent.is_empty() {
+            frame
+        } else {
+            format!("{};{}", parent, frame)
+        };
+        let children: i64 = span
+            .sub_frames
+            .iter()
+            .map(crate::span::Span::duration_micros)
+            .sum();
+        let self_time = (span.duration_micros() - children).max(0);
+        if self_time > 0 {
+            *stacks.entry(stack.clone()).or_insert(0) += self_time;
+        }
+        add_spans(stacks, &stack, &span.sub_frames);
+    }
+}
@@ -0,0 +1,123 @@
+use crate::queue::Queue;
+
+/// Persists every observed span the way Observer 0.1 did: spans of events marked `critical`
+/// are pushed to the `Queue`, the rest are written to `<directory>/<event>/<span key>.json`.
+/// The event name is percent-encoded, but for ASCII letters, digits, `_` and `-`.
+///
+/// Each span is handled on its own, so a critical event called from a non-critical one still
+/// reaches the queue. Spans are stored once, without their sub frames and with the key of
+/// their parent as `parent`. Without a queue critical spans are stored locally too.
+pub struct LocalStore {
+    directory: String,
+    queue: Option<Box<dyn Queue>>,
+}
+
+impl LocalStore {
+    pub fn builder(directory: &str) -> Self {
+        LocalStore {
+            directory: directory.to_string(),
+            queue: None,
+        }
+    }
+
+    pub fn with_queue(mut self, queue: Box<dyn Queue>) -> Self {
+        self.queue = Some(queue);
+        self
+    }
+
+    pub fn build(self) -> Box<Self> {
+        std::fs::create_dir_all(&self.directory).expect("Failed to create local store directory");
+        Box::new(self)
+    }
+
+    /// Stores every span it can, returning the first failure.
+    fn store(&self, parent: &crate::span::Span, spans: &[crate::span::Span]) -> crate::Result<()> {
+        let mut result = Ok(());
+        for span in spans.iter() {
+            let stored = match serde_json::to_value(span.with_sub_frames(vec![])) {
+                Ok(mut data) => {
+                    data["parent"] = json!(parent.get_key());
+                    match &self.queue {
+                        Some(queue) if span.critical => {
+                            queue.enqueue(data);
+                            Ok(())
+                        }
+                        _ => self.save_on_local(span, &data).map_err(|e| {
+                            failure::format_err!("failed to store span {}: {}", span.id, e)
+                        }),
+                    }
+                }
+                Err(e) => Err(failure::format_err!(
+                    "failed to serialize span {}: {}",
+                    span.id,
+                    e
+                )),
+            };
+            let sub_frames = self.store(span, &span.sub_frames);
+            if result.is_ok() {
+                result = stored.and(sub_frames);
+            }
+        }
+        result
+    }
+
+    fn save_on_local(
+        &self,
+        span: &crate::span::Span,
+        data: &serde_json::Value,
+    ) -> std::io::Result<()> {
+        let dir = std::path::Path::new(&self.directory).join(encode(&span.id));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(
+            dir.join(format!("{}.json", span.get_key())),
+            data.to_string(),
+        )
+    }
+}
+
+impl crate::Backend for LocalStore {
+    fn app_started(&self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn app_ended(&self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn context_created(&self, _id: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn context_ended(&self, ctx: &crate::Context) -> crate::Result<()> {
+        let frame = ctx.span_stack.borrow();
+        match frame.first() {
+            Some(frame) => self.store(frame, &frame.sub_frames),
+            None => Ok(()),
+        }
+    }
+
+    fn span_created(&self, _id: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn span_data(&self, _key: &str, _value: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn span_ended(&self, _span: Option<&crate::span::Span>) -> crate::Result<()> {
+        Ok(())
+    }
+}
+
+/// `id` as a single path component, `.` and `..` included.
+fn encode(id: &str) -> String {
+    let mut encoded = String::with_capacity(id.len());
+    for byte in id.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
@@ -1,8 +1,48 @@
+use std::collections::HashSet;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
 static SPACE: usize = 4;
+static DEFAULT_PATTERN: &str = "{d} - {m}{n}";
+
+/// How a finished context is rendered.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// Indented tree of spans, one block per context.
+    Tree,
+    /// One logfmt line per span (and per span log), breadcrumbs as `field.<key>`.
+    Logfmt,
+    /// One JSON object per context, spans nested under `spans`.
+    Json,
+}
+
 pub struct Logger {
     path: Option<String>,
     stdout: bool,
     stderr: bool,
+    format: Format,
+    pattern: String,
+    view: View,
+    truncate: bool,
+    buffer_size: usize,
+    reopen_on_sighup: bool,
+    file: Option<LogFile>,
+}
+
+/// The log file, owned by the logger so the application keeps its own `log` configuration.
+struct LogFile {
+    path: String,
+    buffer_size: usize,
+    writer: Mutex<Option<std::io::BufWriter<std::fs::File>>>,
+    reopen: Arc<AtomicBool>,
+}
+
+/// What part of a context ends up in the output.
+#[derive(Default)]
+pub(crate) struct View {
+    max_depth: Option<usize>,
+    hidden_fields: HashSet<String>,
 }
 
 impl Logger {
@@ -11,6 +51,13 @@ impl Logger {
             path: None,
             stdout: false,
             stderr: false,
+            format: Format::Tree,
+            pattern: DEFAULT_PATTERN.to_string(),
+            view: View::default(),
+            truncate: false,
+            buffer_size: 0,
+            reopen_on_sighup: false,
+            file: None,
         }
     }
 
@@ -29,107 +76,344 @@ impl Logger {
         self
     }
 
-    pub fn build(self) -> Box<Self> {
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Pattern of every entry written to the file, defaults to `"{d} - {m}{n}"`.
+    ///
+    /// `{m}` is the message, `{n}` a newline, `{l}` the level (always `INFO`), `{d}` the local
+    /// time in ISO 8601 and `{d(<chrono format>)}` the local time in that format. `{{` and `}}`
+    /// are literal braces.
+    ///
+    /// Only applies to `Format::Tree`, `Logfmt` and `Json` lines are written as they are so each
+    /// line stays parseable.
+    ///
+    /// `build` fails if a `{d(...)}` format is not a valid chrono format.
+    pub fn with_pattern(mut self, pattern: &str) -> Self {
+        self.pattern = pattern.to_string();
+        self
+    }
+
+    /// Only show spans up to `depth` levels below the context.
+    pub fn with_max_depth(mut self, depth: usize) -> Self {
+        self.view.max_depth = Some(depth);
+        self
+    }
+
+    /// Leave the breadcrumb `key` out of the output, e.g. for sensitive or noisy fields.
+    pub fn hide_field(mut self, key: &str) -> Self {
+        self.view.hidden_fields.insert(key.to_string());
+        self
+    }
+
+    /// Start the file empty instead of appending to it.
+    pub fn with_truncate(mut self) -> Self {
+        self.truncate = true;
+        self
+    }
+
+    /// Buffer up to `bytes` of file output instead of flushing after every entry.
+    ///
+    /// The buffer is flushed when full, on reopen and by `observer::end_app`, so entries can be
+    /// lost if the process dies or exits without calling it.
+    pub fn with_buffer_size(mut self, bytes: usize) -> Self {
+        self.buffer_size = bytes;
+        self
+    }
+
+    /// Reopen the file on `SIGHUP`, for `logrotate` and friends. Only has effect on unix.
+    ///
+    /// Installing the handler means `SIGHUP` no longer terminates the process.
+    pub fn with_reopen_on_sighup(mut self) -> Self {
+        self.reopen_on_sighup = true;
+        self
+    }
+
+    /// Fails if the pattern has an invalid date format, the file can not be opened or the
+    /// `SIGHUP` handler can not be installed.
+    pub fn build(mut self) -> crate::Result<Box<Self>> {
+        if let Some(format) = date_formats(&self.pattern).find(|f| !valid_date_format(f)) {
+            return Err(failure::format_err!(
+                "Invalid date format {:?} in log pattern {:?}",
+                format,
+                self.pattern
+            ));
+        }
         if let Some(path) = &self.path {
-            let requests = log4rs::append::file::FileAppender::builder()
-                .encoder(Box::new(log4rs::encode::pattern::PatternEncoder::new(
-                    "{d} - {m}{n}",
-                )))
-                .append(true)
-                .build(path)
-                .expect("Failed to create file appender");
-
-            let config = log4rs::config::Config::builder()
-                .appender(log4rs::config::Appender::builder().build("requests", Box::new(requests)))
-                .build(
-                    log4rs::config::Root::builder()
-                        .appender("requests")
-                        .build(log::LevelFilter::Info),
-                )
-                .unwrap();
-            log4rs::init_config(config).expect("Failed to create logging builder");
-        }
-        Box::new(self)
+            let file = open(path, self.truncate)
+                .map_err(|e| failure::format_err!("Failed to open log file {}: {}", path, e))?;
+            let file = LogFile {
+                path: path.to_string(),
+                buffer_size: self.buffer_size,
+                writer: Mutex::new(Some(buffered(file, self.buffer_size))),
+                reopen: Arc::new(AtomicBool::new(false)),
+            };
+            #[cfg(unix)]
+            {
+                if self.reopen_on_sighup {
+                    signal_hook::flag::register(signal_hook::consts::SIGHUP, file.reopen.clone())
+                        .map_err(|e| {
+                        failure::format_err!("Failed to register SIGHUP handler: {}", e)
+                    })?;
+                }
+            }
+            self.file = Some(file);
+        }
+        Ok(Box::new(self))
     }
 
     pub(crate) fn handle_log(&self, log: &str) {
-        if self.path.is_some() {
-            info!("{}", log);
+        if let Some(file) = &self.file {
+            file.write(&render(&self.pattern, log));
         }
         if self.stdout {
             println!("{}", log);
         }
+        if self.stderr {
+            eprintln!("{}", log);
+        }
+    }
+
+    /// Writes every line of `lines` as an entry of its own, without the pattern.
+    fn handle_lines(&self, lines: &str) {
+        let mut entry = String::with_capacity(lines.len() + 1);
+        for line in lines.lines().filter(|line| !line.is_empty()) {
+            entry.push_str(line);
+            entry.push('\n');
+        }
+        if entry.is_empty() {
+            return;
+        }
+        if let Some(file) = &self.file {
+            file.write(&entry);
+        }
+        if self.stdout {
+            print!("{}", entry);
+        }
+        if self.stderr {
+            eprint!("{}", entry);
+        }
     }
 }
 
 impl crate::Backend for Logger {
-    fn app_started(&self) {
+    fn app_started(&self) -> crate::Result<()> {
         self.handle_log("logger_initialized");
+        Ok(())
     }
 
-    fn app_ended(&self) {
+    fn app_ended(&self) -> crate::Result<()> {
         // self.handle_log("logger_ended");
+        if let Some(file) = &self.file {
+            file.flush();
+        }
+        Ok(())
     }
 
-    fn context_created(&self, _id: &str) {
+    fn context_created(&self, _id: &str) -> crate::Result<()> {
         // self.handle_log(&format!("context_created with id: {}", id));
+        Ok(())
     }
 
-    fn context_ended(&self, ctx: &crate::Context) {
-        let log = if self.stdout || self.path.is_some() {
-            print_context(ctx)
-        } else {
-            "".to_string()
+    fn context_ended(&self, ctx: &crate::Context) -> crate::Result<()> {
+        if !(self.stdout || self.stderr || self.file.is_some()) {
+            return Ok(());
+        }
+        let log = match self.format {
+            Format::Tree => print_tree(ctx, &self.view),
+            Format::Logfmt => print_logfmt(ctx, &self.view),
+            Format::Json => print_json(ctx, &self.view),
         };
-        self.handle_log(&log);
+        match self.format {
+            Format::Tree => self.handle_log(&log),
+            Format::Logfmt | Format::Json => self.handle_lines(&log),
+        }
+        Ok(())
     }
 
-    fn span_created(&self, _id: &str) {
+    fn span_created(&self, _id: &str) -> crate::Result<()> {
         // self.handle_log(&format!("span_created with id: {}", id));
+        Ok(())
+    }
+
+    fn span_data(&self, _key: &str, _value: &str) -> crate::Result<()> {
+        Ok(())
     }
-    fn span_data(&self, _key: &str, _value: &str) {}
-    fn span_ended(&self, _span: Option<&crate::span::Span>) {
+
+    fn span_ended(&self, _span: Option<&crate::span::Span>) -> crate::Result<()> {
         //        if let Some(span) = span {
         //            self.handle_log(&format!("span_ended with id: {}", span.id));
         //        }
+        Ok(())
+    }
+}
+
+impl LogFile {
+    fn write(&self, entry: &str) {
+        let mut writer = match self.writer.lock() {
+            Ok(writer) => writer,
+            Err(_err) => return,
+        };
+        if self.reopen.swap(false, Ordering::Relaxed) {
+            if let Some(mut old) = writer.take() {
+                let _ = old.flush();
+            }
+        }
+        if writer.is_none() {
+            match open(&self.path, false) {
+                Ok(file) => *writer = Some(buffered(file, self.buffer_size)),
+                Err(e) => {
+                    eprintln!("observer: failed to open {}: {}", self.path, e);
+                    return;
+                }
+            }
+        }
+        if let Some(w) = writer.as_mut() {
+            let mut written = w.write_all(entry.as_bytes());
+            if written.is_ok() && self.buffer_size == 0 {
+                written = w.flush();
+            }
+            if let Err(e) = written {
+                eprintln!("observer: failed to write to {}: {}", self.path, e);
+                // try a fresh file handle for the next entry
+                *writer = None;
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut writer) = self.writer.lock() {
+            if let Some(w) = writer.as_mut() {
+                if let Err(e) = w.flush() {
+                    eprintln!("observer: failed to write to {}: {}", self.path, e);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for LogFile {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+fn open(path: &str, truncate: bool) -> std::io::Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(!truncate)
+        .truncate(truncate)
+        .open(path)
+}
+
+fn buffered(file: std::fs::File, buffer_size: usize) -> std::io::BufWriter<std::fs::File> {
+    if buffer_size == 0 {
+        std::io::BufWriter::new(file)
+    } else {
+        std::io::BufWriter::with_capacity(buffer_size, file)
     }
 }
 
+/// The `<chrono format>` of every `{d(<chrono format>)}` of `pattern`.
+fn date_formats(pattern: &str) -> impl Iterator<Item = &str> {
+    pattern
+        .split("{d(")
+        .skip(1)
+        .filter_map(|rest| rest.find(")}").map(|end| &rest[..end]))
+}
+
+fn valid_date_format(format: &str) -> bool {
+    !chrono::format::StrftimeItems::new(format).any(|item| item == chrono::format::Item::Error)
+}
+
+/// Fills the `with_pattern` placeholders, anything unknown is kept as written.
+fn render(pattern: &str, message: &str) -> String {
+    use std::fmt::Write;
+
+    let mut entry = String::with_capacity(pattern.len() + message.len());
+    let mut rest = pattern;
+    while let Some(start) = rest.find(['{', '}']) {
+        entry.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            entry.push_str(&rest[..1]);
+            rest = &rest[2..];
+            continue;
+        }
+        let end = match rest.find('}') {
+            Some(end) if rest.starts_with('{') => end,
+            _ => {
+                entry.push_str(&rest[..1]);
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        let now = chrono::Local::now();
+        match &rest[1..end] {
+            "m" => entry.push_str(message),
+            "n" => entry.push('\n'),
+            "l" => entry.push_str("INFO"),
+            "d" => entry.push_str(&now.to_rfc3339_opts(chrono::SecondsFormat::Micros, false)),
+            spec if spec.starts_with("d(") && spec.ends_with(')') => {
+                let format = &spec[2..spec.len() - 1];
+                let written = entry.len();
+                if write!(entry, "{}", now.format(format)).is_err() {
+                    entry.truncate(written);
+                    entry.push_str(&rest[..=end]);
+                }
+            }
+            _ => entry.push_str(&rest[..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    entry.push_str(rest);
+    entry
+}
+
+/// The whole context as an indented tree.
 pub(crate) fn print_context(ctx: &crate::Context) -> String {
+    print_tree(ctx, &View::default())
+}
+
+pub(crate) fn print_tree(ctx: &crate::Context, view: &View) -> String {
     let mut writer = "".to_string();
     let frame = ctx.span_stack.borrow();
     if let Some(frame) = frame.first() {
-        let dur = frame
-            .end_time
-            .as_ref()
-            .unwrap_or(&chrono::Utc::now())
-            .signed_duration_since(frame.start_time);
         writer.push_str(&format!(
             "context: {} [{}ms, {}]\n",
             ctx.id(),
-            dur.num_milliseconds(),
+            frame.duration_ms(),
             frame.start_time
         ));
-        print_span(&mut writer, &frame.sub_frames, SPACE);
+        print_span(&mut writer, &frame.sub_frames, SPACE, view);
     }
     writer
 }
 
-pub(crate) fn print_span(writer: &mut String, spans: &[crate::span::Span], space: usize) {
+pub(crate) fn print_span(
+    writer: &mut String,
+    spans: &[crate::span::Span],
+    space: usize,
+    view: &View,
+) {
+    if matches!(view.max_depth, Some(depth) if space > depth * SPACE) {
+        return;
+    }
     for span in spans.iter() {
-        let dur = span
-            .end_time
-            .as_ref()
-            .unwrap_or(&chrono::Utc::now())
-            .signed_duration_since(span.start_time);
         writer.push_str(&format!(
             "{:>space$}{}: {}ms\n",
             "",
             span.id,
-            dur.num_milliseconds(),
+            span.duration_ms(),
             space = space
         ));
         for (key, value) in span.breadcrumbs.iter() {
+            if view.hidden_fields.contains(key) {
+                continue;
+            }
             writer.push_str(&format!(
                 "{:>space$}@{}: {}\n",
                 "",
@@ -180,6 +464,139 @@ pub(crate) fn print_span(writer: &mut String, spans: &[crate::span::Span], space
                 ));
             }
         }
-        print_span(writer, &span.sub_frames, space + SPACE);
+        print_span(writer, &span.sub_frames, space + SPACE, view);
+    }
+}
+
+pub(crate) fn print_logfmt(ctx: &crate::Context, view: &View) -> String {
+    let mut writer = "".to_string();
+    let frame = ctx.span_stack.borrow();
+    if let Some(frame) = frame.first() {
+        logfmt_spans(
+            &mut writer,
+            &ctx.id(),
+            &frame.id,
+            &frame.sub_frames,
+            1,
+            view,
+        );
+    }
+    writer
+}
+
+fn logfmt_spans(
+    writer: &mut String,
+    context: &str,
+    parent: &str,
+    spans: &[crate::span::Span],
+    depth: usize,
+    view: &View,
+) {
+    if matches!(view.max_depth, Some(max) if depth > max) {
+        return;
+    }
+    for span in spans.iter() {
+        let mut line = vec![
+            logfmt_pair("ts", &span.start_time.to_rfc3339()),
+            logfmt_pair("context", context),
+            logfmt_pair("span", &span.id),
+            logfmt_pair("parent", parent),
+            logfmt_pair("depth", &depth.to_string()),
+            logfmt_pair("duration_ms", &span.duration_ms().to_string()),
+        ];
+        let mut breadcrumbs: Vec<_> = span
+            .breadcrumbs
+            .iter()
+            .filter(|(key, _)| !view.hidden_fields.contains(*key))
+            .collect();
+        breadcrumbs.sort_by(|a, b| a.0.cmp(b.0));
+        for (key, value) in breadcrumbs {
+            line.push(logfmt_pair(&format!("field.{}", key), &json_text(value)));
+        }
+        if let Some(success) = span.success {
+            line.push(logfmt_pair("success", &success.to_string()));
+        }
+        if let Some(result) = &span.result {
+            line.push(logfmt_pair("result", &json_text(result)));
+        }
+        if let Some(err) = &span.err {
+            line.push(logfmt_pair("error", err));
+        }
+        writer.push_str(&line.join(" "));
+        writer.push('\n');
+
+        for (at, log) in span.logs.iter() {
+            writer.push_str(&format!(
+                "{} {} {} {}\n",
+                logfmt_pair("ts", &at.to_rfc3339()),
+                logfmt_pair("context", context),
+                logfmt_pair("span", &span.id),
+                logfmt_pair("log", log)
+            ));
+        }
+        logfmt_spans(writer, context, &span.id, &span.sub_frames, depth + 1, view);
+    }
+}
+
+fn json_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.to_string(),
+        v => v.to_string(),
+    }
+}
+
+/// `key=value`, quoting the value when it is empty or has spaces, quotes, `=` or control chars.
+fn logfmt_pair(key: &str, value: &str) -> String {
+    let plain = !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c == ' ' || c == '"' || c == '=' || c == '\\' || c.is_control());
+    if plain {
+        format!("{}={}", key, value)
+    } else {
+        format!("{}={}", key, json!(value))
+    }
+}
+
+pub(crate) fn print_json(ctx: &crate::Context, view: &View) -> String {
+    let frame = ctx.span_stack.borrow();
+    match frame.first() {
+        Some(frame) => json!({
+            "context": ctx.id(),
+            "start_time": frame.start_time,
+            "duration_ms": frame.duration_ms(),
+            "spans": json_spans(&frame.sub_frames, 1, view),
+        })
+        .to_string(),
+        None => "".to_string(),
+    }
+}
+
+fn json_spans(spans: &[crate::span::Span], depth: usize, view: &View) -> serde_json::Value {
+    if matches!(view.max_depth, Some(max) if depth > max) {
+        return json!([]);
     }
+    let spans: Vec<_> = spans
+        .iter()
+        .map(|span| {
+            let breadcrumbs: serde_json::Map<_, _> = span
+                .breadcrumbs
+                .iter()
+                .filter(|(key, _)| !view.hidden_fields.contains(*key))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect();
+            json!({
+                "id": span.id,
+                "start_time": span.start_time,
+                "duration_ms": span.duration_ms(),
+                "breadcrumbs": breadcrumbs,
+                "success": span.success,
+                "result": span.result,
+                "error": span.err,
+                "logs": span.logs.iter().map(|(at, log)| json!({"at": at, "log": log})).collect::<Vec<_>>(),
+                "spans": json_spans(&span.sub_frames, depth + 1, view),
+            })
+        })
+        .collect();
+    json!(spans)
 }
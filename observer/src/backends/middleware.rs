@@ -0,0 +1,482 @@
+//! Backends wrapping other backends.
+//!
+//! ```ignore
+//! use observer::backends::middleware::{Filter, Redact, Route};
+//!
+//! let analysis = Filter::builder(sql).with_namespace("observer__pg").build();
+//! let logger = Redact::builder(logger).with_redacted("password").build();
+//! let backend = Route::builder()
+//!     .with_route("admin_", admin_logger)
+//!     .with_default(logger)
+//!     .build();
+//! ```
+//!
+//! Patterns are matched against the whole id, `*` matches any run of characters and `?` a
+//! single one.
+use crate::span::Span;
+use crate::{Backend, Context};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::thread::ThreadId;
+
+static REDACTED: &str = "[REDACTED]";
+
+/// State of the context active on each thread, contexts are thread local.
+struct PerThread<T: Copy>(Mutex<HashMap<ThreadId, T>>);
+
+impl<T: Copy> PerThread<T> {
+    fn new() -> Self {
+        PerThread(Mutex::new(HashMap::new()))
+    }
+
+    fn set(&self, value: T) {
+        if let Ok(mut map) = self.0.lock() {
+            map.insert(std::thread::current().id(), value);
+        }
+    }
+
+    fn get(&self) -> Option<T> {
+        match self.0.lock() {
+            Ok(map) => map.get(&std::thread::current().id()).copied(),
+            Err(_err) => None,
+        }
+    }
+
+    fn remove(&self) {
+        if let Ok(mut map) = self.0.lock() {
+            map.remove(&std::thread::current().id());
+        }
+    }
+}
+
+/// Passes on only contexts whose id matches a context pattern, and of those only the spans
+/// whose id matches a span pattern or whose namespace (the id up to the last `__`) matches a
+/// namespace pattern. Without patterns of a kind everything of that kind passes.
+///
+/// Spans dropped from a finished context take their matching sub spans with them up to the
+/// nearest kept ancestor. A matching context left without spans is still passed on, without
+/// spans, so the inner backend ends every context it was told was created.
+pub struct Filter {
+    inner: Box<dyn Backend>,
+    contexts: Vec<String>,
+    spans: Vec<String>,
+    namespaces: Vec<String>,
+    active: PerThread<bool>,
+}
+
+impl Filter {
+    pub fn builder(inner: Box<dyn Backend>) -> Self {
+        Filter {
+            inner,
+            contexts: vec![],
+            spans: vec![],
+            namespaces: vec![],
+            active: PerThread::new(),
+        }
+    }
+
+    pub fn with_context(mut self, pattern: &str) -> Self {
+        self.contexts.push(pattern.to_string());
+        self
+    }
+
+    pub fn with_span(mut self, pattern: &str) -> Self {
+        self.spans.push(pattern.to_string());
+        self
+    }
+
+    pub fn with_namespace(mut self, pattern: &str) -> Self {
+        self.namespaces.push(pattern.to_string());
+        self
+    }
+
+    pub fn build(self) -> Box<Self> {
+        Box::new(self)
+    }
+
+    fn context_matches(&self, id: &str) -> bool {
+        self.contexts.is_empty() || self.contexts.iter().any(|p| glob(p, id))
+    }
+
+    fn filters_spans(&self) -> bool {
+        !(self.spans.is_empty() && self.namespaces.is_empty())
+    }
+
+    fn span_matches(&self, id: &str) -> bool {
+        if !self.filters_spans() {
+            return true;
+        }
+        self.spans.iter().any(|p| glob(p, id))
+            || matches!(id.rsplit_once("__"), Some((namespace, _))
+                if self.namespaces.iter().any(|p| glob(p, namespace)))
+    }
+
+    fn prune(&self, spans: &[Span]) -> Vec<Span> {
+        let mut kept = vec![];
+        for span in spans.iter() {
+            let sub_frames = self.prune(&span.sub_frames);
+            if self.span_matches(&span.id) {
+                kept.push(span.with_sub_frames(sub_frames));
+            } else {
+                kept.extend(sub_frames);
+            }
+        }
+        kept
+    }
+}
+
+impl Backend for Filter {
+    fn app_started(&self) -> crate::Result<()> {
+        self.inner.app_started()
+    }
+
+    fn app_ended(&self) -> crate::Result<()> {
+        self.inner.app_ended()
+    }
+
+    fn context_created(&self, id: &str) -> crate::Result<()> {
+        let matches = self.context_matches(id);
+        self.active.set(matches);
+        if matches {
+            return self.inner.context_created(id);
+        }
+        Ok(())
+    }
+
+    fn context_ended(&self, ctx: &Context) -> crate::Result<()> {
+        self.active.remove();
+        if !self.context_matches(&ctx.id()) {
+            return Ok(());
+        }
+        if !self.filters_spans() {
+            return self.inner.context_ended(ctx);
+        }
+        let spans = match ctx.span_stack.borrow().first() {
+            Some(frame) => self.prune(&frame.sub_frames),
+            None => vec![],
+        };
+        self.inner.context_ended(&ctx.with_spans(spans))
+    }
+
+    fn span_created(&self, id: &str) -> crate::Result<()> {
+        if self.active.get() == Some(true) && self.span_matches(id) {
+            return self.inner.span_created(id);
+        }
+        Ok(())
+    }
+
+    fn span_data(&self, key: &str, value: &str) -> crate::Result<()> {
+        if self.active.get() == Some(true) {
+            return self.inner.span_data(key, value);
+        }
+        Ok(())
+    }
+
+    fn span_ended(&self, span: Option<&Span>) -> crate::Result<()> {
+        // `span` is the parent, the span that ended is its last sub frame
+        let ended = span.and_then(|parent| parent.sub_frames.last());
+        if self.active.get() == Some(true) && matches!(ended, Some(s) if self.span_matches(&s.id)) {
+            return self.inner.span_ended(span);
+        }
+        Ok(())
+    }
+}
+
+/// Replaces the values of breadcrumbs matching a redacted pattern with `"[REDACTED]"`, and
+/// leaves out breadcrumbs matching a dropped pattern, before passing anything on.
+///
+/// The same applies to the keys of objects in span results. The text values of redacted and
+/// dropped breadcrumbs are also replaced wherever they show up in the result, error or logs of
+/// their span.
+pub struct Redact {
+    inner: Box<dyn Backend>,
+    redacted: Vec<String>,
+    dropped: Vec<String>,
+}
+
+impl Redact {
+    pub fn builder(inner: Box<dyn Backend>) -> Self {
+        Redact {
+            inner,
+            redacted: vec![],
+            dropped: vec![],
+        }
+    }
+
+    pub fn with_redacted(mut self, key: &str) -> Self {
+        self.redacted.push(key.to_string());
+        self
+    }
+
+    pub fn with_dropped(mut self, key: &str) -> Self {
+        self.dropped.push(key.to_string());
+        self
+    }
+
+    pub fn build(self) -> Box<Self> {
+        Box::new(self)
+    }
+
+    fn is_dropped(&self, key: &str) -> bool {
+        self.dropped.iter().any(|p| glob(p, key))
+    }
+
+    fn is_redacted(&self, key: &str) -> bool {
+        self.redacted.iter().any(|p| glob(p, key))
+    }
+
+    fn clean(&self, span: &Span) -> Span {
+        let mut span =
+            span.with_sub_frames(span.sub_frames.iter().map(|s| self.clean(s)).collect());
+        let secrets: Vec<String> = span
+            .breadcrumbs
+            .iter()
+            .filter(|(key, _)| self.is_dropped(key) || self.is_redacted(key))
+            .filter_map(|(_, value)| match value {
+                serde_json::Value::String(text) if !text.is_empty() => Some(text.clone()),
+                _ => None,
+            })
+            .collect();
+        span.breadcrumbs.retain(|key, _| !self.is_dropped(key));
+        for (key, value) in span.breadcrumbs.iter_mut() {
+            if self.is_redacted(key) {
+                *value = json!(REDACTED);
+            }
+        }
+        if let Some(result) = span.result.as_mut() {
+            self.clean_value(result, &secrets);
+        }
+        span.err = span.err.map(|err| scrub(&err, &secrets));
+        for (_, log) in span.logs.iter_mut() {
+            *log = scrub(log, &secrets);
+        }
+        span
+    }
+
+    fn clean_value(&self, value: &mut serde_json::Value, secrets: &[String]) {
+        match value {
+            serde_json::Value::Object(map) => {
+                map.retain(|key, _| !self.is_dropped(key));
+                for (key, value) in map.iter_mut() {
+                    if self.is_redacted(key) {
+                        *value = json!(REDACTED);
+                    } else {
+                        self.clean_value(value, secrets);
+                    }
+                }
+            }
+            serde_json::Value::Array(items) => {
+                for item in items.iter_mut() {
+                    self.clean_value(item, secrets);
+                }
+            }
+            serde_json::Value::String(text) => *text = scrub(text, secrets),
+            _ => {}
+        }
+    }
+}
+
+impl Backend for Redact {
+    fn app_started(&self) -> crate::Result<()> {
+        self.inner.app_started()
+    }
+
+    fn app_ended(&self) -> crate::Result<()> {
+        self.inner.app_ended()
+    }
+
+    fn context_created(&self, id: &str) -> crate::Result<()> {
+        self.inner.context_created(id)
+    }
+
+    fn context_ended(&self, ctx: &Context) -> crate::Result<()> {
+        let spans = match ctx.span_stack.borrow().first() {
+            Some(frame) => frame.sub_frames.iter().map(|s| self.clean(s)).collect(),
+            None => vec![],
+        };
+        self.inner.context_ended(&ctx.with_spans(spans))
+    }
+
+    fn span_created(&self, id: &str) -> crate::Result<()> {
+        self.inner.span_created(id)
+    }
+
+    fn span_data(&self, key: &str, value: &str) -> crate::Result<()> {
+        if self.is_dropped(key) {
+            return Ok(());
+        }
+        if self.is_redacted(key) {
+            self.inner.span_data(key, REDACTED)
+        } else {
+            self.inner.span_data(key, value)
+        }
+    }
+
+    fn span_ended(&self, span: Option<&Span>) -> crate::Result<()> {
+        let span = span.map(|s| self.clean(s));
+        self.inner.span_ended(span.as_ref())
+    }
+}
+
+/// Sends each context, and its spans, to the backend of the longest prefix its id starts with,
+/// or to the default backend if none does. Contexts without a backend are dropped.
+pub struct Route {
+    routes: Vec<(String, Box<dyn Backend>)>,
+    default: Option<Box<dyn Backend>>,
+    current: PerThread<Option<usize>>,
+}
+
+impl Route {
+    pub fn builder() -> Self {
+        Route {
+            routes: vec![],
+            default: None,
+            current: PerThread::new(),
+        }
+    }
+
+    pub fn with_route(mut self, prefix: &str, backend: Box<dyn Backend>) -> Self {
+        self.routes.push((prefix.to_string(), backend));
+        self
+    }
+
+    pub fn with_default(mut self, backend: Box<dyn Backend>) -> Self {
+        self.default = Some(backend);
+        self
+    }
+
+    pub fn build(self) -> Box<Self> {
+        Box::new(self)
+    }
+
+    /// Index into `routes` for `id`, `routes.len()` meaning the default backend.
+    fn pick(&self, id: &str) -> Option<usize> {
+        let route = self
+            .routes
+            .iter()
+            .enumerate()
+            .filter(|(_, (prefix, _))| id.starts_with(prefix.as_str()))
+            .max_by_key(|(_, (prefix, _))| prefix.len())
+            .map(|(i, _)| i);
+        match route {
+            Some(i) => Some(i),
+            None if self.default.is_some() => Some(self.routes.len()),
+            None => None,
+        }
+    }
+
+    fn backend(&self, index: Option<usize>) -> Option<&dyn Backend> {
+        match index {
+            Some(i) if i < self.routes.len() => Some(self.routes[i].1.as_ref()),
+            Some(_) => self.default.as_deref(),
+            None => None,
+        }
+    }
+
+    fn current(&self) -> Option<&dyn Backend> {
+        self.backend(self.current.get().flatten())
+    }
+
+    fn all(&self) -> impl Iterator<Item = &Box<dyn Backend>> {
+        self.routes
+            .iter()
+            .map(|(_, b)| b)
+            .chain(self.default.iter())
+    }
+}
+
+impl Backend for Route {
+    fn app_started(&self) -> crate::Result<()> {
+        // every backend is called, the first error is returned
+        let mut result = Ok(());
+        for backend in self.all() {
+            let outcome = backend.app_started();
+            if result.is_ok() {
+                result = outcome;
+            }
+        }
+        result
+    }
+
+    fn app_ended(&self) -> crate::Result<()> {
+        // every backend is called, the first error is returned
+        let mut result = Ok(());
+        for backend in self.all() {
+            let outcome = backend.app_ended();
+            if result.is_ok() {
+                result = outcome;
+            }
+        }
+        result
+    }
+
+    fn context_created(&self, id: &str) -> crate::Result<()> {
+        let index = self.pick(id);
+        self.current.set(index);
+        if let Some(backend) = self.backend(index) {
+            return backend.context_created(id);
+        }
+        Ok(())
+    }
+
+    fn context_ended(&self, ctx: &Context) -> crate::Result<()> {
+        self.current.remove();
+        if let Some(backend) = self.backend(self.pick(&ctx.id())) {
+            return backend.context_ended(ctx);
+        }
+        Ok(())
+    }
+
+    fn span_created(&self, id: &str) -> crate::Result<()> {
+        if let Some(backend) = self.current() {
+            return backend.span_created(id);
+        }
+        Ok(())
+    }
+
+    fn span_data(&self, key: &str, value: &str) -> crate::Result<()> {
+        if let Some(backend) = self.current() {
+            return backend.span_data(key, value);
+        }
+        Ok(())
+    }
+
+    fn span_ended(&self, span: Option<&Span>) -> crate::Result<()> {
+        if let Some(backend) = self.current() {
+            return backend.span_ended(span);
+        }
+        Ok(())
+    }
+}
+
+/// `text` with every occurrence of a secret replaced by `"[REDACTED]"`.
+fn scrub(text: &str, secrets: &[String]) -> String {
+    secrets.iter().fold(text.to_string(), |text, secret| {
+        text.replace(secret.as_str(), REDACTED)
+    })
+}
+
+/// Whether `text` matches `pattern`, where `*` matches any run of characters and `?` any one.
+fn glob(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` and of the text it was tried at, to backtrack to
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
@@ -1 +1,12 @@
+pub mod batch;
+pub mod chrome_trace;
+pub mod flamegraph;
+pub mod local_store;
 pub mod logger;
+pub mod middleware;
+pub mod prometheus;
+#[cfg(feature = "sentry")]
+pub mod sentry;
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+pub mod sql;
+pub mod syslog;
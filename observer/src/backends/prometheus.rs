@@ -0,0 +1,351 @@
+use std::collections::{BTreeMap, HashSet};
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+
+static DEFAULT_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+static OVERFLOW_VALUE: &str = "__other__";
+/// Labels set by the backend itself.
+static RESERVED_LABELS: &[&str] = &["span", "le", "outcome"];
+
+/// In-process aggregator of span metrics, rendered in the Prometheus text exposition format.
+///
+/// Every finished span contributes to `observer_span_duration_seconds` (histogram) and
+/// `observer_span_total` (counter by outcome), labelled with the span id and any breadcrumbs
+/// opted in with `with_label`.
+pub struct Prometheus {
+    buckets: Vec<f64>,
+    labels: Vec<(String, usize)>,
+    listen: Option<String>,
+    metrics: Metrics,
+}
+
+impl Prometheus {
+    pub fn builder() -> Self {
+        Prometheus {
+            buckets: DEFAULT_BUCKETS.to_vec(),
+            labels: vec![],
+            listen: None,
+            metrics: Metrics::new(DEFAULT_BUCKETS.to_vec(), vec![]),
+        }
+    }
+
+    /// Upper bounds (in seconds) of the duration histogram buckets.
+    pub fn with_buckets(mut self, buckets: &[f64]) -> Self {
+        let mut buckets = buckets.to_vec();
+        buckets.retain(|b| b.is_finite());
+        buckets.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        buckets.dedup();
+        self.buckets = buckets;
+        self
+    }
+
+    /// Use the breadcrumb `key` as a label. Once `max_values` distinct values have been seen,
+    /// further values are reported as `__other__` to keep cardinality bounded.
+    ///
+    /// Panics if the label name is `span`, `le` or `outcome`, which the backend sets itself, or
+    /// is already used by another label.
+    pub fn with_label(mut self, key: &str, max_values: usize) -> Self {
+        let name = label_name(key);
+        if RESERVED_LABELS.contains(&name.as_str()) {
+            panic!("Label {:?} of breadcrumb {:?} is reserved", name, key);
+        }
+        if let Some((other, _)) = self.labels.iter().find(|(k, _)| label_name(k) == name) {
+            panic!(
+                "Breadcrumbs {:?} and {:?} both map to label {:?}",
+                other, key, name
+            );
+        }
+        self.labels.push((key.to_string(), max_values));
+        self
+    }
+
+    /// Serve `/metrics` over HTTP on `addr` (e.g. `"0.0.0.0:9102"`) once the app has started.
+    pub fn with_listener(mut self, addr: &str) -> Self {
+        self.listen = Some(addr.to_string());
+        self
+    }
+
+    pub fn build(mut self) -> Box<Self> {
+        self.metrics = Metrics::new(self.buckets.clone(), self.labels.clone());
+        Box::new(self)
+    }
+
+    /// Handle to the aggregated metrics, usable after the backend is moved into the observer.
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.clone()
+    }
+}
+
+impl crate::Backend for Prometheus {
+    fn app_started(&self) -> crate::Result<()> {
+        if let Some(addr) = &self.listen {
+            if let Err(e) = serve(addr, self.metrics()) {
+                eprintln!("observer: prometheus listener on {} failed: {}", addr, e);
+            }
+        }
+        Ok(())
+    }
+
+    fn app_ended(&self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn context_created(&self, _id: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn context_ended(&self, ctx: &crate::Context) -> crate::Result<()> {
+        let frame = ctx.span_stack.borrow();
+        if let Some(frame) = frame.first() {
+            if let Ok(mut registry) = self.metrics.inner.lock() {
+                registry.observe_spans(&frame.sub_frames);
+            }
+        }
+        Ok(())
+    }
+
+    fn span_created(&self, _id: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn span_data(&self, _key: &str, _value: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn span_ended(&self, _span: Option<&crate::span::Span>) -> crate::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct Metrics {
+    inner: Arc<Mutex<Registry>>,
+}
+
+impl Metrics {
+    fn new(buckets: Vec<f64>, labels: Vec<(String, usize)>) -> Self {
+        let labels = labels
+            .into_iter()
+            .map(|(key, max_values)| Label {
+                name: label_name(&key),
+                key,
+                max_values,
+                seen: HashSet::new(),
+            })
+            .collect();
+        Metrics {
+            inner: Arc::new(Mutex::new(Registry {
+                buckets,
+                labels,
+                histograms: BTreeMap::new(),
+                counters: BTreeMap::new(),
+            })),
+        }
+    }
+
+    /// Current state of all metrics in the Prometheus text exposition format (version 0.0.4).
+    pub fn render(&self) -> String {
+        match self.inner.lock() {
+            Ok(registry) => registry.render(),
+            Err(_err) => "".to_string(),
+        }
+    }
+}
+
+struct Label {
+    key: String,
+    name: String,
+    max_values: usize,
+    seen: HashSet<String>,
+}
+
+struct Histogram {
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+/// Span id followed by the value of every configured label, in order.
+type Series = Vec<String>;
+
+struct Registry {
+    buckets: Vec<f64>,
+    labels: Vec<Label>,
+    histograms: BTreeMap<Series, Histogram>,
+    counters: BTreeMap<(Series, &'static str), u64>,
+}
+
+impl Registry {
+    fn observe_spans(&mut self, spans: &[crate::span::Span]) {
+        for span in spans.iter() {
+            self.observe_span(span);
+            self.observe_spans(&span.sub_frames);
+        }
+    }
+
+    fn observe_span(&mut self, span: &crate::span::Span) {
+        let mut series = vec![span.id.clone()];
+        for label in self.labels.iter_mut() {
+            series.push(label.value(span.breadcrumbs.get(&label.key)));
+        }
+
+        let seconds = span.duration_micros() as f64 / 1_000_000.0;
+        let buckets = &self.buckets;
+        let histogram = self
+            .histograms
+            .entry(series.clone())
+            .or_insert_with(|| Histogram {
+                counts: vec![0; buckets.len()],
+                sum: 0.0,
+                count: 0,
+            });
+        for (i, bound) in buckets.iter().enumerate() {
+            if seconds <= *bound {
+                histogram.counts[i] += 1;
+            }
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+
+        let outcome = match span.success {
+            Some(true) => "success",
+            Some(false) => "failure",
+            None => "unknown",
+        };
+        *self.counters.entry((series, outcome)).or_insert(0) += 1;
+    }
+
+    fn labels(&self, series: &[String], extra: Option<(&str, &str)>) -> String {
+        let mut pairs = vec![format!("span=\"{}\"", escape(&series[0]))];
+        for (label, value) in self.labels.iter().zip(series[1..].iter()) {
+            pairs.push(format!("{}=\"{}\"", label.name, escape(value)));
+        }
+        if let Some((name, value)) = extra {
+            pairs.push(format!("{}=\"{}\"", name, escape(value)));
+        }
+        pairs.join(",")
+    }
+
+    fn render(&self) -> String {
+        let mut writer = "".to_string();
+        writer.push_str(
+            "# HELP observer_span_duration_seconds Duration of observed spans in seconds.\n",
+        );
+        writer.push_str("# TYPE observer_span_duration_seconds histogram\n");
+        for (series, histogram) in self.histograms.iter() {
+            for (bound, count) in self.buckets.iter().zip(histogram.counts.iter()) {
+                writer.push_str(&format!(
+                    "observer_span_duration_seconds_bucket{{{}}} {}\n",
+                    self.labels(series, Some(("le", &bound.to_string()))),
+                    count
+                ));
+            }
+            writer.push_str(&format!(
+                "observer_span_duration_seconds_bucket{{{}}} {}\n",
+                self.labels(series, Some(("le", "+Inf"))),
+                histogram.count
+            ));
+            writer.push_str(&format!(
+                "observer_span_duration_seconds_sum{{{}}} {}\n",
+                self.labels(series, None),
+                histogram.sum
+            ));
+            writer.push_str(&format!(
+                "observer_span_duration_seconds_count{{{}}} {}\n",
+                self.labels(series, None),
+                histogram.count
+            ));
+        }
+
+        writer
+            .push_str("# HELP observer_span_total Number of finished observed spans by outcome.\n");
+        writer.push_str("# TYPE observer_span_total counter\n");
+        for ((series, outcome), count) in self.counters.iter() {
+            writer.push_str(&format!(
+                "observer_span_total{{{}}} {}\n",
+                self.labels(series, Some(("outcome", outcome))),
+                count
+            ));
+        }
+        writer
+    }
+}
+
+impl Label {
+    fn value(&mut self, breadcrumb: Option<&serde_json::Value>) -> String {
+        let value = match breadcrumb {
+            Some(serde_json::Value::String(s)) => s.to_string(),
+            Some(v) => v.to_string(),
+            None => return "".to_string(),
+        };
+        if self.seen.contains(&value) {
+            return value;
+        }
+        if self.seen.len() >= self.max_values {
+            return OVERFLOW_VALUE.to_string();
+        }
+        self.seen.insert(value.clone());
+        value
+    }
+}
+
+/// Breadcrumb keys are free-form, label names must match `[a-zA-Z_][a-zA-Z0-9_]*`.
+fn label_name(key: &str) -> String {
+    let mut name: String = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        name.insert(0, '_');
+    }
+    name
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Minimal HTTP listener answering `GET /metrics`, everything else gets a 404.
+fn serve(addr: &str, metrics: Metrics) -> std::io::Result<()> {
+    let listener = std::net::TcpListener::bind(addr)?;
+    std::thread::Builder::new()
+        .name("observer-prometheus".to_string())
+        .spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let _ = respond(&mut stream, &metrics);
+            }
+        })?;
+    Ok(())
+}
+
+fn respond(stream: &mut std::net::TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(std::time::Duration::from_secs(5)))?;
+    let mut request = vec![];
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 8192 {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut parts = request.lines().next().unwrap_or("").split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.render()),
+        _ => ("404 Not Found", "".to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
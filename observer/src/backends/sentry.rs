@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+static QUEUE_SIZE: usize = 100;
+static CLIENT: &str = concat!("observer/", env!("CARGO_PKG_VERSION"));
+
+/// Reports every failed span (`success == Some(false)`) as a Sentry error event.
+///
+/// Events carry the error, the breadcrumbs of the enclosing spans, the span logs, the context id
+/// and the configured tags. They are posted as envelopes from a background thread; identical
+/// errors are sent once per `dedup_window` and at most `max_events_per_minute` go out.
+///
+/// `app_ended` waits up to `shutdown_timeout` for the queued events to be sent, so call
+/// `observer::end_app` before the process exits.
+pub struct Sentry {
+    dsn: String,
+    environment: Option<String>,
+    release: Option<String>,
+    tags: HashMap<String, String>,
+    dedup_window: Duration,
+    max_events_per_minute: usize,
+    shutdown_timeout: Duration,
+    limiter: Mutex<Limiter>,
+    sender: Mutex<Option<SyncSender<String>>>,
+    worker: Mutex<Option<std::thread::JoinHandle<()>>>,
+}
+
+struct Limiter {
+    seen: HashMap<String, Instant>,
+    window_start: Instant,
+    window_count: usize,
+}
+
+struct Dsn {
+    endpoint: String,
+    public_key: String,
+}
+
+impl Sentry {
+    /// `dsn` is the project DSN, e.g. `https://<key>@o0.ingest.sentry.io/<project>`.
+    pub fn builder(dsn: &str) -> Self {
+        Sentry {
+            dsn: dsn.to_string(),
+            environment: None,
+            release: None,
+            tags: HashMap::new(),
+            dedup_window: Duration::from_secs(60),
+            max_events_per_minute: 60,
+            shutdown_timeout: Duration::from_secs(2),
+            limiter: Mutex::new(Limiter {
+                seen: HashMap::new(),
+                window_start: Instant::now(),
+                window_count: 0,
+            }),
+            sender: Mutex::new(None),
+            worker: Mutex::new(None),
+        }
+    }
+
+    pub fn with_environment(mut self, environment: &str) -> Self {
+        self.environment = Some(environment.to_string());
+        self
+    }
+
+    pub fn with_release(mut self, release: &str) -> Self {
+        self.release = Some(release.to_string());
+        self
+    }
+
+    pub fn with_tag(mut self, key: &str, value: &str) -> Self {
+        self.tags.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// The same error from the same span id is not reported again within `window`.
+    pub fn with_dedup_window(mut self, window: Duration) -> Self {
+        self.dedup_window = window;
+        self
+    }
+
+    pub fn with_max_events_per_minute(mut self, max: usize) -> Self {
+        self.max_events_per_minute = max;
+        self
+    }
+
+    /// Longest time `app_ended` waits for the queued events to be sent, 2 seconds by default.
+    pub fn with_shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Fails if the DSN is not valid or the sender thread can not be started.
+    pub fn build(self) -> crate::Result<Box<Self>> {
+        let dsn = parse_dsn(&self.dsn)
+            .ok_or_else(|| failure::format_err!("Invalid sentry DSN {:?}", self.dsn))?;
+        let (sender, receiver) = sync_channel(QUEUE_SIZE);
+        let worker = std::thread::Builder::new()
+            .name("observer-sentry".to_string())
+            .spawn(move || send_envelopes(dsn, receiver))?;
+        if let Ok(mut s) = self.sender.lock() {
+            s.replace(sender);
+        }
+        self.worker
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .replace(worker);
+        Ok(Box::new(self))
+    }
+
+    fn allow(&self, fingerprint: &str) -> bool {
+        let mut limiter = match self.limiter.lock() {
+            Ok(limiter) => limiter,
+            Err(_err) => return false,
+        };
+        let now = Instant::now();
+        let window = self.dedup_window;
+        limiter
+            .seen
+            .retain(|_, at| now.duration_since(*at) < window);
+        if limiter.seen.contains_key(fingerprint) {
+            return false;
+        }
+        if now.duration_since(limiter.window_start) >= Duration::from_secs(60) {
+            limiter.window_start = now;
+            limiter.window_count = 0;
+        }
+        if limiter.window_count >= self.max_events_per_minute {
+            return false;
+        }
+        limiter.window_count += 1;
+        limiter.seen.insert(fingerprint.to_string(), now);
+        true
+    }
+
+    fn report(&self, ctx: &crate::Context, trail: &[&crate::span::Span], span: &crate::span::Span) {
+        let fingerprint = format!("{}\u{0}{}", span.id, title(span));
+        if !self.allow(&fingerprint) {
+            return;
+        }
+        let envelope = self.envelope(ctx, trail, span);
+        if let Ok(sender) = self.sender.lock() {
+            if let Some(sender) = sender.as_ref() {
+                // queue full means sentry is not keeping up, dropping is better than blocking
+                let _ = sender.try_send(envelope);
+            }
+        }
+    }
+
+    /// The envelope of the error event of the failed `span`, `trail` being its enclosing spans.
+    pub(crate) fn envelope(
+        &self,
+        ctx: &crate::Context,
+        trail: &[&crate::span::Span],
+        span: &crate::span::Span,
+    ) -> String {
+        let err = span.err.clone().unwrap_or_default();
+        let title = title(span);
+        let event_id = uuid::Uuid::new_v4().simple().to_string();
+        let timestamp = span.end_time.unwrap_or_else(chrono::Utc::now);
+        let mut breadcrumbs = vec![];
+        for parent in trail.iter() {
+            breadcrumbs.push(json!({
+                "timestamp": parent.start_time.to_rfc3339(),
+                "category": "observer.span",
+                "message": parent.id,
+                "data": parent.breadcrumbs,
+            }));
+        }
+        for (at, log) in span.logs.iter() {
+            breadcrumbs.push(json!({
+                "timestamp": at.to_rfc3339(),
+                "category": "observer.log",
+                "message": log,
+            }));
+        }
+        let mut tags = self.tags.clone();
+        tags.insert("context_id".to_string(), ctx.id());
+        tags.insert("span_id".to_string(), span.id.clone());
+
+        let event = json!({
+            "event_id": event_id,
+            "timestamp": timestamp.to_rfc3339(),
+            "platform": "native",
+            "level": "error",
+            "logger": "observer",
+            "transaction": span.id,
+            "environment": self.environment,
+            "release": self.release,
+            "exception": {
+                "values": [{"type": span.id, "value": title}],
+            },
+            "tags": tags,
+            "extra": {
+                "error": err,
+                "breadcrumbs": span.breadcrumbs,
+                "result": span.result,
+                "start_time": span.start_time.to_rfc3339(),
+            },
+            "breadcrumbs": {"values": breadcrumbs},
+            "fingerprint": [span.id, title],
+        })
+        .to_string();
+        format!(
+            "{}\n{}\n{}\n",
+            json!({"event_id": event_id, "sent_at": chrono::Utc::now().to_rfc3339()}),
+            json!({"type": "event", "length": event.len()}),
+            event
+        )
+    }
+
+    fn report_spans<'a>(
+        &self,
+        ctx: &crate::Context,
+        trail: &mut Vec<&'a crate::span::Span>,
+        spans: &'a [crate::span::Span],
+    ) {
+        for span in spans.iter() {
+            if span.success == Some(false) {
+                self.report(ctx, trail, span);
+            }
+            trail.push(span);
+            self.report_spans(ctx, trail, &span.sub_frames);
+            trail.pop();
+        }
+    }
+}
+
+impl crate::Backend for Sentry {
+    fn app_started(&self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn app_ended(&self) -> crate::Result<()> {
+        // the sender thread stops once the queue is drained and the sender is gone
+        self.sender.lock().unwrap_or_else(|e| e.into_inner()).take();
+        let worker = match self.worker.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            Some(worker) => worker,
+            None => return Ok(()),
+        };
+        let deadline = Instant::now() + self.shutdown_timeout;
+        while !worker.is_finished() {
+            if Instant::now() >= deadline {
+                return Err(failure::format_err!(
+                    "sentry events still not sent after {:?}",
+                    self.shutdown_timeout
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        worker
+            .join()
+            .map_err(|_| failure::format_err!("sentry sender thread panicked"))
+    }
+
+    fn context_created(&self, _id: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn context_ended(&self, ctx: &crate::Context) -> crate::Result<()> {
+        let frame = ctx.span_stack.borrow();
+        if let Some(frame) = frame.first() {
+            self.report_spans(ctx, &mut vec![], &frame.sub_frames);
+        }
+        Ok(())
+    }
+
+    fn span_created(&self, _id: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn span_data(&self, _key: &str, _value: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn span_ended(&self, _span: Option<&crate::span::Span>) -> crate::Result<()> {
+        Ok(())
+    }
+}
+
+/// `Debug` of an error may carry a backtrace, only its first line identifies it.
+fn title(span: &crate::span::Span) -> String {
+    let err = span.err.as_deref().unwrap_or_default();
+    err.lines().next().unwrap_or("").to_string()
+}
+
+/// `{scheme}://{public_key}@{host}/{path/}{project_id}`
+fn parse_dsn(dsn: &str) -> Option<Dsn> {
+    let (scheme, rest) = dsn.split_once("://")?;
+    let (public_key, rest) = rest.split_once('@')?;
+    let public_key = public_key.split(':').next()?;
+    let (host, path) = rest.split_once('/')?;
+    let path = path.trim_end_matches('/');
+    let (prefix, project_id) = match path.rsplit_once('/') {
+        Some((prefix, project_id)) => (format!("/{}", prefix), project_id),
+        None => ("".to_string(), path),
+    };
+    if public_key.is_empty() || host.is_empty() || project_id.is_empty() {
+        return None;
+    }
+    Some(Dsn {
+        endpoint: format!(
+            "{}://{}{}/api/{}/envelope/",
+            scheme, host, prefix, project_id
+        ),
+        public_key: public_key.to_string(),
+    })
+}
+
+fn send_envelopes(dsn: Dsn, receiver: Receiver<String>) {
+    let auth = format!(
+        "Sentry sentry_version=7, sentry_client={}, sentry_key={}",
+        CLIENT, dsn.public_key
+    );
+    let mut blocked_until: Option<Instant> = None;
+    for envelope in receiver.iter() {
+        if let Some(until) = blocked_until {
+            if Instant::now() < until {
+                continue;
+            }
+            blocked_until = None;
+        }
+        let response = ureq::post(&dsn.endpoint)
+            .set("X-Sentry-Auth", &auth)
+            .set("Content-Type", "application/x-sentry-envelope")
+            .timeout(Duration::from_secs(10))
+            .send_string(&envelope);
+        match response {
+            Ok(_) => {}
+            Err(ureq::Error::Status(429, response)) => {
+                let retry_after = response
+                    .header("Retry-After")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(60);
+                blocked_until = Some(Instant::now() + Duration::from_secs(retry_after));
+            }
+            Err(e) => eprintln!("observer: failed to send event to sentry: {}", e),
+        }
+    }
+}
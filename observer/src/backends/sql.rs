@@ -0,0 +1,398 @@
+use crate::schema::{ColumnType, Table};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+enum Target {
+    #[cfg(feature = "sqlite")]
+    Sqlite(String),
+    #[cfg(feature = "postgres")]
+    Postgres(String),
+}
+
+enum Database {
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Connection),
+    #[cfg(feature = "postgres")]
+    Postgres(Box<postgres::Client>),
+}
+
+/// Stores every finished span of an event in observer.json as a row of that event's table,
+/// see `schema::Table` for the columns.
+///
+/// Tables are created on `build`, and columns for fields added to observer.json since are
+/// added to existing tables. Columns of removed fields are kept and left empty. Spans of
+/// events not in observer.json and breadcrumbs not declared as fields are not stored.
+///
+/// Values not of their column's type are stored as `NULL` and reported as an error of the
+/// backend.
+pub struct Sql {
+    events_path: String,
+    target: Option<Target>,
+    tables: HashMap<String, Table>,
+    database: Option<Mutex<Database>>,
+}
+
+impl Sql {
+    pub fn builder(events_path: &str) -> Self {
+        Sql {
+            events_path: events_path.to_string(),
+            target: None,
+            tables: HashMap::new(),
+            database: None,
+        }
+    }
+
+    /// SQLite database file at `path`, created if missing.
+    #[cfg(feature = "sqlite")]
+    pub fn with_sqlite(mut self, path: &str) -> Self {
+        self.target = Some(Target::Sqlite(path.to_string()));
+        self
+    }
+
+    /// Postgres database at `url`, e.g. `postgresql://user@localhost/observer`.
+    #[cfg(feature = "postgres")]
+    pub fn with_postgres(mut self, url: &str) -> Self {
+        self.target = Some(Target::Postgres(url.to_string()));
+        self
+    }
+
+    /// Fails if the events file can not be read, or the database not opened or migrated.
+    pub fn build(mut self) -> crate::Result<Box<Self>> {
+        self.tables = crate::schema::tables(&self.events_path)
+            .map_err(|e| failure::format_err!("Not able to load {}: {}", self.events_path, e))?;
+        let mut database = match self.target.as_ref() {
+            #[cfg(feature = "sqlite")]
+            Some(Target::Sqlite(path)) => Database::Sqlite(rusqlite::Connection::open(path)?),
+            #[cfg(feature = "postgres")]
+            Some(Target::Postgres(url)) => {
+                Database::Postgres(Box::new(postgres::Client::connect(url, postgres::NoTls)?))
+            }
+            None => return Err(failure::format_err!("No database given")),
+        };
+        for table in self.tables.values() {
+            migrate(&mut database, table).map_err(|e| {
+                failure::format_err!("Failed to create table {}: {}", table.name, e)
+            })?;
+        }
+        self.database = Some(Mutex::new(database));
+        Ok(Box::new(self))
+    }
+
+    /// `mismatches` gets the values not stored because they are not of their column's type.
+    fn rows<'a>(
+        &'a self,
+        ctx: &crate::Context,
+        parent: &crate::span::Span,
+        rows: &mut Vec<(&'a Table, Vec<Option<Cell>>)>,
+        mismatches: &mut Vec<String>,
+    ) {
+        for span in parent.sub_frames.iter() {
+            if let Some(table) = self.tables.get(&span.id) {
+                rows.push((table, row(ctx, parent, span, table, mismatches)));
+            }
+            self.rows(ctx, span, rows, mismatches);
+        }
+    }
+
+    fn insert(&self, rows: &[(&Table, Vec<Option<Cell>>)]) -> crate::Result<()> {
+        let database = match &self.database {
+            Some(database) => database,
+            None => return Ok(()),
+        };
+        let mut database = match database.lock() {
+            Ok(database) => database,
+            Err(_err) => return Ok(()),
+        };
+        match &mut *database {
+            #[cfg(feature = "sqlite")]
+            Database::Sqlite(connection) => {
+                let transaction = connection.transaction()?;
+                for (table, cells) in rows.iter() {
+                    transaction.execute(
+                        &insert_statement(table, |i| format!("?{}", i)),
+                        rusqlite::params_from_iter(cells.iter()),
+                    )?;
+                }
+                transaction.commit()?;
+            }
+            #[cfg(feature = "postgres")]
+            Database::Postgres(client) => {
+                let mut transaction = client.transaction()?;
+                for (table, cells) in rows.iter() {
+                    let params: Vec<_> = table
+                        .columns
+                        .iter()
+                        .zip(cells.iter())
+                        .map(|(column, cell)| pg_param(column.kind, cell))
+                        .collect();
+                    let params: Vec<_> = params.iter().map(|p| p.as_ref()).collect();
+                    transaction.execute(
+                        insert_statement(table, |i| format!("${}", i)).as_str(),
+                        &params,
+                    )?;
+                }
+                transaction.commit()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl crate::Backend for Sql {
+    fn app_started(&self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn app_ended(&self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn context_created(&self, _id: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn context_ended(&self, ctx: &crate::Context) -> crate::Result<()> {
+        let (mut rows, mut mismatches) = (vec![], vec![]);
+        {
+            let frame = ctx.span_stack.borrow();
+            if let Some(frame) = frame.first() {
+                self.rows(ctx, frame, &mut rows, &mut mismatches);
+            }
+        }
+        if rows.is_empty() {
+            return Ok(());
+        }
+        self.insert(&rows)
+            .map_err(|e| failure::format_err!("failed to store context {}: {}", ctx.id(), e))?;
+        if !mismatches.is_empty() {
+            return Err(failure::format_err!(
+                "stored context {} without {}",
+                ctx.id(),
+                mismatches.join(", ")
+            ));
+        }
+        Ok(())
+    }
+
+    fn span_created(&self, _id: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn span_data(&self, _key: &str, _value: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn span_ended(&self, _span: Option<&crate::span::Span>) -> crate::Result<()> {
+        Ok(())
+    }
+}
+
+/// A column value, `None` is stored as `NULL`.
+enum Cell {
+    Integer(i64),
+    Real(f64),
+    Boolean(bool),
+    Text(String),
+    Timestamp(chrono::DateTime<chrono::Utc>),
+    Json(serde_json::Value),
+}
+
+impl Cell {
+    /// `value` as `kind`, `None` when it is missing or null, an error when it is of another
+    /// type.
+    fn from_json(
+        kind: ColumnType,
+        value: Option<&serde_json::Value>,
+    ) -> Result<Option<Cell>, String> {
+        let value = match value {
+            None | Some(serde_json::Value::Null) => return Ok(None),
+            Some(value) => value,
+        };
+        let cell = match kind {
+            ColumnType::Integer => value.as_i64().map(Cell::Integer),
+            ColumnType::Real => value.as_f64().map(Cell::Real),
+            ColumnType::Boolean => value.as_bool().map(Cell::Boolean),
+            ColumnType::Text => match value {
+                serde_json::Value::String(s) => Some(Cell::Text(s.to_string())),
+                v => Some(Cell::Text(v.to_string())),
+            },
+            ColumnType::Timestamp => value
+                .as_str()
+                .and_then(|s| chrono::DateTime::parse_from_rfc3339(s).ok())
+                .map(|t| Cell::Timestamp(t.with_timezone(&chrono::Utc))),
+            ColumnType::Json => Some(Cell::Json(value.clone())),
+        };
+        cell.map(Some)
+            .ok_or_else(|| format!("{} is not {:?}", value, kind))
+    }
+}
+
+fn row(
+    ctx: &crate::Context,
+    parent: &crate::span::Span,
+    span: &crate::span::Span,
+    table: &Table,
+    mismatches: &mut Vec<String>,
+) -> Vec<Option<Cell>> {
+    let mut cell = |kind, value, column: &str| {
+        Cell::from_json(kind, value).unwrap_or_else(|e| {
+            mismatches.push(format!("{}.{}: {}", span.id, column, e));
+            None
+        })
+    };
+    let duration = span.end_time.map(|_| span.duration_ms());
+    // in `schema::FIXED_COLUMNS` order, then result and fields
+    let mut cells = vec![
+        Some(Cell::Text(span.get_key())),
+        Some(Cell::Text(parent.get_key())),
+        Some(Cell::Text(ctx.id())),
+        Some(Cell::Text(ctx.get_key())),
+        Some(Cell::Timestamp(span.start_time)),
+        span.end_time.map(Cell::Timestamp),
+        duration.map(Cell::Integer),
+        span.success.map(Cell::Boolean),
+        span.err.clone().map(Cell::Text),
+    ];
+    let result_kind = table.columns[cells.len()].kind;
+    cells.push(cell(result_kind, span.result.as_ref(), "result"));
+    let offset = cells.len();
+    for (i, field) in table.fields.iter().enumerate() {
+        let kind = table.columns[offset + i].kind;
+        cells.push(cell(kind, span.breadcrumbs.get(field), field));
+    }
+    cells
+}
+
+fn insert_statement(table: &Table, placeholder: impl Fn(usize) -> String) -> String {
+    let columns: Vec<_> = table
+        .columns
+        .iter()
+        .map(|c| format!("\"{}\"", c.name))
+        .collect();
+    let values: Vec<_> = (1..=columns.len()).map(placeholder).collect();
+    format!(
+        "INSERT INTO \"{}\" ({}) VALUES ({})",
+        table.name,
+        columns.join(", "),
+        values.join(", ")
+    )
+}
+
+fn create_statement(table: &Table, sql_type: fn(ColumnType) -> &'static str) -> String {
+    let columns: Vec<_> = table
+        .columns
+        .iter()
+        .map(|c| {
+            if c.name == "span_key" {
+                format!("\"{}\" {} PRIMARY KEY", c.name, sql_type(c.kind))
+            } else {
+                format!("\"{}\" {}", c.name, sql_type(c.kind))
+            }
+        })
+        .collect();
+    format!(
+        "CREATE TABLE IF NOT EXISTS \"{}\" ({})",
+        table.name,
+        columns.join(", ")
+    )
+}
+
+fn migrate(database: &mut Database, table: &Table) -> crate::Result<()> {
+    match database {
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(connection) => {
+            connection.execute(&create_statement(table, sqlite_type), [])?;
+            let mut statement = connection.prepare(&format!(
+                "SELECT name FROM pragma_table_info('{}')",
+                table.name
+            ))?;
+            let existing = statement
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            for column in table.columns.iter() {
+                if !existing.contains(&column.name) {
+                    connection.execute(
+                        &format!(
+                            "ALTER TABLE \"{}\" ADD COLUMN \"{}\" {}",
+                            table.name,
+                            column.name,
+                            sqlite_type(column.kind)
+                        ),
+                        [],
+                    )?;
+                }
+            }
+        }
+        #[cfg(feature = "postgres")]
+        Database::Postgres(client) => {
+            let mut statements = vec![create_statement(table, postgres_type)];
+            for column in table.columns.iter() {
+                statements.push(format!(
+                    "ALTER TABLE \"{}\" ADD COLUMN IF NOT EXISTS \"{}\" {}",
+                    table.name,
+                    column.name,
+                    postgres_type(column.kind)
+                ));
+            }
+            client.batch_execute(&statements.join(";\n"))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite_type(kind: ColumnType) -> &'static str {
+    match kind {
+        ColumnType::Integer | ColumnType::Boolean => "INTEGER",
+        ColumnType::Real => "REAL",
+        ColumnType::Text | ColumnType::Timestamp | ColumnType::Json => "TEXT",
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl rusqlite::ToSql for Cell {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        use rusqlite::types::ToSqlOutput;
+        Ok(match self {
+            Cell::Integer(i) => ToSqlOutput::from(*i),
+            Cell::Real(f) => ToSqlOutput::from(*f),
+            Cell::Boolean(b) => ToSqlOutput::from(*b),
+            Cell::Text(s) => ToSqlOutput::from(s.as_str()),
+            Cell::Timestamp(t) => {
+                ToSqlOutput::from(t.to_rfc3339_opts(chrono::SecondsFormat::Micros, true))
+            }
+            Cell::Json(v) => ToSqlOutput::from(v.to_string()),
+        })
+    }
+}
+
+#[cfg(feature = "postgres")]
+fn postgres_type(kind: ColumnType) -> &'static str {
+    match kind {
+        ColumnType::Integer => "BIGINT",
+        ColumnType::Real => "DOUBLE PRECISION",
+        ColumnType::Boolean => "BOOLEAN",
+        ColumnType::Text => "TEXT",
+        ColumnType::Timestamp => "TIMESTAMPTZ",
+        ColumnType::Json => "JSONB",
+    }
+}
+
+/// Postgres needs `NULL`s typed like their column.
+#[cfg(feature = "postgres")]
+fn pg_param(kind: ColumnType, cell: &Option<Cell>) -> Box<dyn postgres::types::ToSql + Sync> {
+    match (kind, cell) {
+        (_, Some(Cell::Integer(i))) => Box::new(*i),
+        (_, Some(Cell::Real(f))) => Box::new(*f),
+        (_, Some(Cell::Boolean(b))) => Box::new(*b),
+        (_, Some(Cell::Text(s))) => Box::new(s.clone()),
+        (_, Some(Cell::Timestamp(t))) => Box::new(*t),
+        (_, Some(Cell::Json(v))) => Box::new(v.clone()),
+        (ColumnType::Integer, None) => Box::new(None::<i64>),
+        (ColumnType::Real, None) => Box::new(None::<f64>),
+        (ColumnType::Boolean, None) => Box::new(None::<bool>),
+        (ColumnType::Text, None) => Box::new(None::<String>),
+        (ColumnType::Timestamp, None) => Box::new(None::<chrono::DateTime<chrono::Utc>>),
+        (ColumnType::Json, None) => Box::new(None::<serde_json::Value>),
+    }
+}
@@ -0,0 +1,385 @@
+use std::io::Write;
+use std::sync::Mutex;
+
+/// IANA "example" enterprise number, used to namespace our structured-data ids.
+static ENTERPRISE_ID: &str = "32473";
+static NIL: &str = "-";
+
+#[derive(Clone, Copy, Debug)]
+pub enum Facility {
+    Kern = 0,
+    User = 1,
+    Daemon = 3,
+    Auth = 4,
+    Local0 = 16,
+    Local1 = 17,
+    Local2 = 18,
+    Local3 = 19,
+    Local4 = 20,
+    Local5 = 21,
+    Local6 = 22,
+    Local7 = 23,
+}
+
+#[derive(Clone, Copy)]
+enum Severity {
+    Error = 3,
+    Info = 6,
+}
+
+enum Transport {
+    /// Local syslog daemon socket, usually `/dev/log`.
+    #[cfg(unix)]
+    Unix(String),
+    Udp(String),
+    /// Messages are framed with octet counting (RFC 6587), so multi-line messages are safe.
+    Tcp(String),
+}
+
+enum Connection {
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixDatagram),
+    Udp(std::net::UdpSocket),
+    Tcp(std::net::TcpStream),
+}
+
+/// Sends finished contexts as RFC 5424 messages to a syslog daemon.
+///
+/// By default there is one message per context, with the `Logger` tree as message text. With
+/// `per_span` every observed span becomes its own message instead. Context id, span id, duration
+/// and breadcrumbs are carried as structured data in both modes, per context the span ids are
+/// listed in order and breadcrumbs are named `<span id>.<key>`.
+///
+/// Messages go to `/dev/log` by default on unix, to UDP port 514 of the local host elsewhere.
+pub struct Syslog {
+    transport: Transport,
+    facility: Facility,
+    app_name: String,
+    hostname: String,
+    per_span: bool,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl Syslog {
+    pub fn builder() -> Self {
+        Syslog {
+            transport: default_transport(),
+            facility: Facility::User,
+            app_name: "observer".to_string(),
+            hostname: hostname(),
+            per_span: false,
+            connection: Mutex::new(None),
+        }
+    }
+
+    #[cfg(unix)]
+    pub fn with_unix_socket(mut self, path: &str) -> Self {
+        self.transport = Transport::Unix(path.to_string());
+        self
+    }
+
+    pub fn with_udp(mut self, addr: &str) -> Self {
+        self.transport = Transport::Udp(addr.to_string());
+        self
+    }
+
+    pub fn with_tcp(mut self, addr: &str) -> Self {
+        self.transport = Transport::Tcp(addr.to_string());
+        self
+    }
+
+    pub fn with_facility(mut self, facility: Facility) -> Self {
+        self.facility = facility;
+        self
+    }
+
+    pub fn with_app_name(mut self, app_name: &str) -> Self {
+        self.app_name = header_field(app_name, 48);
+        self
+    }
+
+    /// Send one message per span instead of one per context.
+    pub fn per_span(mut self) -> Self {
+        self.per_span = true;
+        self
+    }
+
+    /// Fails if the syslog daemon can not be reached.
+    pub fn build(self) -> crate::Result<Box<Self>> {
+        let connection = connect(&self.transport)
+            .map_err(|e| failure::format_err!("Failed to connect to syslog: {}", e))?;
+        self.connection
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .replace(connection);
+        Ok(Box::new(self))
+    }
+
+    fn message(
+        &self,
+        severity: Severity,
+        msg_id: &str,
+        sd: &str,
+        timestamp: &chrono::DateTime<chrono::Utc>,
+        msg: &str,
+    ) -> String {
+        format!(
+            "<{}>1 {} {} {} {} {} {} {}",
+            self.facility as u8 * 8 + severity as u8,
+            timestamp.to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+            self.hostname,
+            self.app_name,
+            std::process::id(),
+            msg_id,
+            sd,
+            msg
+        )
+    }
+
+    fn send(&self, message: &str) -> std::io::Result<()> {
+        let mut connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+        let sent = match connection.as_mut() {
+            Some(c) => write(c, message),
+            None => Err(std::io::ErrorKind::NotConnected.into()),
+        };
+        if sent.is_ok() {
+            return Ok(());
+        }
+        // the daemon may have restarted, reconnect once and retry
+        *connection = None;
+        let mut c = connect(&self.transport)?;
+        write(&mut c, message)?;
+        *connection = Some(c);
+        Ok(())
+    }
+
+    fn send_spans(&self, ctx: &crate::Context, spans: &[crate::span::Span]) -> std::io::Result<()> {
+        for span in spans.iter() {
+            let severity = match span.success {
+                Some(false) => Severity::Error,
+                _ => Severity::Info,
+            };
+            let mut msg = format!("{}: {}ms", span.id, span.duration_ms());
+            if let Some(err) = &span.err {
+                msg.push_str(&format!(" error: {}", err));
+            }
+            let mut params = vec![
+                ("context_id", ctx.id()),
+                ("span_id", span.id.clone()),
+                ("duration_ms", span.duration_ms().to_string()),
+            ];
+            if let Some(success) = span.success {
+                params.push(("success", success.to_string()));
+            }
+            let sd = format!(
+                "{}{}",
+                sd_element("observer", &params),
+                breadcrumbs_element(span)
+            );
+            self.send(&self.message(severity, "span", &sd, &span.start_time, &msg))?;
+            self.send_spans(ctx, &span.sub_frames)?;
+        }
+        Ok(())
+    }
+}
+
+impl crate::Backend for Syslog {
+    fn app_started(&self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn app_ended(&self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn context_created(&self, _id: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn context_ended(&self, ctx: &crate::Context) -> crate::Result<()> {
+        if self.per_span {
+            let frame = ctx.span_stack.borrow();
+            if let Some(frame) = frame.first() {
+                self.send_spans(ctx, &frame.sub_frames)?;
+            }
+            return Ok(());
+        }
+
+        let (severity, sd, start_time) = {
+            let frame = ctx.span_stack.borrow();
+            let frame = match frame.first() {
+                Some(frame) => frame,
+                None => return Ok(()),
+            };
+            let severity = if any_failed(&frame.sub_frames) {
+                Severity::Error
+            } else {
+                Severity::Info
+            };
+            let sd = format!(
+                "{}{}",
+                sd_element(
+                    "observer",
+                    &[
+                        ("context_id", ctx.id()),
+                        ("duration_ms", frame.duration_ms().to_string()),
+                    ],
+                ),
+                spans_elements(&frame.sub_frames)
+            );
+            (severity, sd, frame.start_time)
+        };
+        let msg = crate::backends::logger::print_context(ctx);
+        self.send(&self.message(severity, "context", &sd, &start_time, msg.trim_end()))?;
+        Ok(())
+    }
+
+    fn span_created(&self, _id: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn span_data(&self, _key: &str, _value: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn span_ended(&self, _span: Option<&crate::span::Span>) -> crate::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn default_transport() -> Transport {
+    Transport::Unix("/dev/log".to_string())
+}
+
+#[cfg(not(unix))]
+fn default_transport() -> Transport {
+    Transport::Udp("127.0.0.1:514".to_string())
+}
+
+fn connect(transport: &Transport) -> std::io::Result<Connection> {
+    Ok(match transport {
+        #[cfg(unix)]
+        Transport::Unix(path) => {
+            let socket = std::os::unix::net::UnixDatagram::unbound()?;
+            socket.connect(path)?;
+            Connection::Unix(socket)
+        }
+        Transport::Udp(addr) => {
+            let socket = std::net::UdpSocket::bind("0.0.0.0:0")?;
+            socket.connect(addr)?;
+            Connection::Udp(socket)
+        }
+        Transport::Tcp(addr) => Connection::Tcp(std::net::TcpStream::connect(addr)?),
+    })
+}
+
+fn write(connection: &mut Connection, message: &str) -> std::io::Result<()> {
+    match connection {
+        #[cfg(unix)]
+        Connection::Unix(socket) => socket.send(message.as_bytes()).map(|_| ()),
+        Connection::Udp(socket) => socket.send(message.as_bytes()).map(|_| ()),
+        Connection::Tcp(stream) => {
+            write!(stream, "{} {}", message.len(), message)?;
+            stream.flush()
+        }
+    }
+}
+
+fn any_failed(spans: &[crate::span::Span]) -> bool {
+    spans
+        .iter()
+        .any(|span| span.success == Some(false) || any_failed(&span.sub_frames))
+}
+
+/// The ids of `spans` and their sub spans in order, and their breadcrumbs as `<span id>.<key>`.
+fn spans_elements(spans: &[crate::span::Span]) -> String {
+    fn collect(
+        spans: &[crate::span::Span],
+        ids: &mut Vec<(&'static str, String)>,
+        breadcrumbs: &mut Vec<(String, String)>,
+    ) {
+        for span in spans.iter() {
+            ids.push(("span_id", span.id.clone()));
+            let mut own: Vec<(String, String)> = span
+                .breadcrumbs
+                .iter()
+                .map(|(key, value)| (format!("{}.{}", span.id, key), param_value(value)))
+                .collect();
+            own.sort();
+            breadcrumbs.extend(own);
+            collect(&span.sub_frames, ids, breadcrumbs);
+        }
+    }
+
+    let (mut ids, mut breadcrumbs) = (vec![], vec![]);
+    collect(spans, &mut ids, &mut breadcrumbs);
+    let mut elements = "".to_string();
+    if !ids.is_empty() {
+        elements.push_str(&sd_element("spans", &ids));
+    }
+    if !breadcrumbs.is_empty() {
+        elements.push_str(&sd_element("breadcrumbs", &breadcrumbs));
+    }
+    elements
+}
+
+fn param_value(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.to_string(),
+        v => v.to_string(),
+    }
+}
+
+fn breadcrumbs_element(span: &crate::span::Span) -> String {
+    if span.breadcrumbs.is_empty() {
+        return "".to_string();
+    }
+    let mut params: Vec<(&str, String)> = span
+        .breadcrumbs
+        .iter()
+        .map(|(key, value)| (key.as_str(), param_value(value)))
+        .collect();
+    params.sort();
+    sd_element("breadcrumbs", &params)
+}
+
+/// `[name@enterprise key="value" ...]`, param names are restricted like header fields and
+/// `"`, `\` and `]` are escaped in values.
+fn sd_element<K: AsRef<str>>(name: &str, params: &[(K, String)]) -> String {
+    let mut element = format!("[{}@{}", name, ENTERPRISE_ID);
+    for (key, value) in params.iter() {
+        element.push_str(&format!(
+            " {}=\"{}\"",
+            header_field(key.as_ref(), 32).replace('=', "_"),
+            value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace(']', "\\]")
+        ));
+    }
+    element.push(']');
+    element
+}
+
+/// Header fields are printable US-ASCII without spaces, `NIL` when empty.
+fn header_field(value: &str, max_len: usize) -> String {
+    let value: String = value
+        .chars()
+        .filter(|c| c.is_ascii_graphic() && *c != ']' && *c != '"')
+        .take(max_len)
+        .collect();
+    if value.is_empty() {
+        NIL.to_string()
+    } else {
+        value
+    }
+}
+
+fn hostname() -> String {
+    let name = std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .unwrap_or_default();
+    header_field(name.trim(), 255)
+}
@@ -9,7 +9,7 @@ pub struct Context {
 }
 
 thread_local! {
-    static CONTEXT: std::cell::RefCell<Option<Context>> = std::cell::RefCell::new(None);
+    static CONTEXT: std::cell::RefCell<Option<Context>> = const { std::cell::RefCell::new(None) };
 }
 
 impl Context {
@@ -21,6 +21,39 @@ impl Context {
         }
     }
 
+    /// Deep copy of the context, e.g. for backends keeping it past `context_ended`.
+    pub(crate) fn snapshot(&self) -> Context {
+        Context {
+            id: self.id.clone(),
+            key: self.key.clone(),
+            span_stack: std::cell::RefCell::new(
+                self.span_stack
+                    .borrow()
+                    .iter()
+                    .map(Span::snapshot)
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Copy of a finished context with `spans` as its observed spans, for backends passing on
+    /// an edited context.
+    pub(crate) fn with_spans(&self, spans: Vec<Span>) -> Context {
+        let root = match self.span_stack.borrow().first() {
+            Some(frame) => frame.with_sub_frames(spans),
+            None => {
+                let mut frame = Span::new("main");
+                frame.sub_frames = spans;
+                frame
+            }
+        };
+        Context {
+            id: self.id.clone(),
+            key: self.key.clone(),
+            span_stack: std::cell::RefCell::new(vec![root]),
+        }
+    }
+
     pub fn id(&self) -> String {
         self.id.to_string()
     }
@@ -54,6 +87,14 @@ impl Context {
         }
     }
 
+    pub(crate) fn observe_span_default_result(&self, value: impl serde::Serialize) {
+        if let Some(frame) = self.span_stack.borrow_mut().last_mut() {
+            if frame.result.is_none() {
+                frame.set_result(value);
+            }
+        }
+    }
+
     pub(crate) fn span_log(&self, value: &str) {
         let frame = self.span_stack.borrow_mut().pop();
         if let Some(mut frame) = frame {
@@ -62,11 +103,23 @@ impl Context {
         }
     }
 
-    pub fn end_span(&self, _is_critical: bool, err: Option<String>) {
+    pub(crate) fn try_span_log(&self, value: &str) {
+        if let Ok(mut stack) = self.span_stack.try_borrow_mut() {
+            if let Some(frame) = stack.last_mut() {
+                frame.add_logs(value);
+            }
+        }
+    }
+
+    pub fn end_span(&self, is_critical: bool, err: Option<String>) {
         let child = self.span_stack.borrow_mut().pop();
         let parent = self.span_stack.borrow_mut().pop();
         if let Some(mut child_frame) = child {
-            child_frame.set_success(err.is_none()).set_err(err).end();
+            child_frame
+                .set_success(err.is_none())
+                .set_critical(is_critical)
+                .set_err(err)
+                .end();
             if let Some(mut parent_frame) = parent {
                 parent_frame.sub_frames.push(child_frame);
                 self.span_stack.borrow_mut().push(parent_frame);
@@ -0,0 +1,70 @@
+//! Failures of backends, which never reach the observed code.
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static ERRORS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    /// The backend returned an error.
+    Failed,
+    /// The backend panicked, the panic was caught.
+    Panicked,
+    /// The call took longer than `Observer::with_slow_call_threshold`.
+    Slow,
+}
+
+#[derive(Clone, Debug)]
+pub struct BackendError {
+    /// Position of the backend in the order it was added to the observer.
+    pub backend: usize,
+    /// `Backend` method that failed, e.g. `context_ended`.
+    pub method: &'static str,
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl std::fmt::Display for BackendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let kind = match self.kind {
+            ErrorKind::Failed => "failed",
+            ErrorKind::Panicked => "panicked",
+            ErrorKind::Slow => "was slow",
+        };
+        write!(
+            f,
+            "backend {} {} in {}: {}",
+            self.backend, kind, self.method, self.message
+        )
+    }
+}
+
+/// Number of backend failures since the process started.
+pub fn error_count() -> u64 {
+    ERRORS.load(Ordering::Relaxed)
+}
+
+/// Counts the error, and sends it to `errors` if given, or prints it otherwise.
+///
+/// Sending never blocks, errors are dropped when the channel is full.
+pub(crate) fn report(
+    errors: Option<&std::sync::mpsc::SyncSender<BackendError>>,
+    error: BackendError,
+) {
+    ERRORS.fetch_add(1, Ordering::Relaxed);
+    match errors {
+        Some(errors) => {
+            let _ = errors.try_send(error);
+        }
+        None => eprintln!("observer: {}", error),
+    }
+}
+
+pub(crate) fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
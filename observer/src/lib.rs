@@ -12,6 +12,8 @@ extern crate observer_attribute;
 
 pub mod backends;
 pub mod context;
+pub mod errors;
+pub mod log_bridge;
 // #[cfg(feature = "mysql")]
 // pub mod mysql;
 pub mod observe;
@@ -19,31 +21,38 @@ pub mod observe_fields;
 // #[cfg(feature = "postgres")]
 // pub mod pg;
 pub mod prelude;
+pub mod queue;
+pub mod resulty;
+pub mod schema;
 pub mod span;
+pub mod testing;
+#[cfg(feature = "tracing")]
+pub mod tracing_bridge;
 // mod sql_parse;
 
 pub use crate::context::Context;
 
-#[macro_use]
-extern crate log;
-
 #[cfg(test)]
 mod tests;
 
 pub type Result<T> = std::result::Result<T, failure::Error>;
 
+/// Errors and panics of backends are caught and reported by the observer, see `errors`, they
+/// never reach the observed code or the other backends.
 pub trait Backend: Send + Sync {
-    fn app_started(&self);
-    fn app_ended(&self);
-    fn context_created(&self, id: &str);
-    fn context_ended(&self, ctx: &crate::Context);
-    fn span_created(&self, id: &str);
-    fn span_data(&self, key: &str, value: &str);
-    fn span_ended(&self, span: Option<&crate::span::Span>);
+    fn app_started(&self) -> Result<()>;
+    fn app_ended(&self) -> Result<()>;
+    fn context_created(&self, id: &str) -> Result<()>;
+    fn context_ended(&self, ctx: &crate::Context) -> Result<()>;
+    fn span_created(&self, id: &str) -> Result<()>;
+    fn span_data(&self, key: &str, value: &str) -> Result<()>;
+    fn span_ended(&self, span: Option<&crate::span::Span>) -> Result<()>;
 }
 
 pub struct Observer {
     backends: Vec<Box<dyn Backend>>,
+    errors: Option<std::sync::mpsc::SyncSender<errors::BackendError>>,
+    slow_call: Option<std::time::Duration>,
 }
 
 lazy_static! {
@@ -52,66 +61,82 @@ lazy_static! {
 }
 
 thread_local! {
-    static CONTEXT: std::cell::RefCell<Option<Context>> = std::cell::RefCell::new(None);
+    static CONTEXT: std::cell::RefCell<Option<Context>> = const { std::cell::RefCell::new(None) };
+}
+
+/// The global observer, recovering the lock if a thread panicked while holding it.
+fn observer() -> std::sync::RwLockReadGuard<'static, Option<Observer>> {
+    OBSERVER.read().unwrap_or_else(|e| e.into_inner())
 }
 
 pub fn builder(backend: Box<dyn Backend>) -> Observer {
     Observer::builder(backend)
 }
 
+/// Ends the app: every backend gets `app_ended`, to send or write what it still buffers, and is
+/// dropped. Call it once before the process exits, the observer is static and never dropped
+/// otherwise. Spans observed after it are not recorded, until an observer is initialized again.
+pub fn end_app() {
+    let ended = OBSERVER.write().unwrap_or_else(|e| e.into_inner()).take();
+    if let Some(observer) = ended {
+        observer.each("app_ended", |backend| backend.app_ended());
+    }
+}
+
 pub fn create_context(context_id: &str) {
-    match OBSERVER.as_ref().read() {
-        Ok(obj) => {
-            if let Some(obj) = obj.as_ref() {
-                obj.create_context(context_id);
-            }
-        }
-        Err(_err) => {}
-    };
+    if let Some(obj) = observer().as_ref() {
+        obj.create_context(context_id);
+    }
 }
 
 pub fn end_context() {
-    match OBSERVER.as_ref().read() {
-        Ok(obj) => {
-            if let Some(obj) = obj.as_ref() {
-                obj.end_context();
-            }
-        }
-        Err(_err) => {}
-    };
+    if let Some(obj) = observer().as_ref() {
+        obj.end_context();
+    }
 }
 
 pub fn observe_span_log(value: &str) {
-    match OBSERVER.as_ref().read() {
-        Ok(obj) => {
-            if let Some(obj) = obj.as_ref() {
-                obj.span_log(value);
+    if let Some(obj) = observer().as_ref() {
+        obj.span_log(value);
+    }
+}
+
+/// Whether a context is active on this thread and not in use, e.g. by a backend handling it,
+/// so spans can be started and ended.
+#[cfg(feature = "tracing")]
+pub(crate) fn context_available() -> bool {
+    CONTEXT
+        .try_with(|ctx| match ctx.try_borrow() {
+            Ok(ctx) => {
+                matches!(ctx.as_ref(), Some(ctx) if ctx.span_stack.try_borrow_mut().is_ok())
             }
-        }
-        Err(_err) => {}
-    };
+            Err(_err) => false,
+        })
+        .unwrap_or(false)
 }
 
-pub(crate) fn start_span(id: &str) {
-    match OBSERVER.as_ref().read() {
-        Ok(obj) => {
-            if let Some(obj) = obj.as_ref() {
-                obj.create_span(id);
+/// `observe_span_log` that drops the log instead of panicking when the context is in use, e.g.
+/// when a backend logs while handling it.
+pub(crate) fn try_span_log(value: &str) {
+    let _ = CONTEXT.try_with(|ctx| {
+        if let Ok(ctx) = ctx.try_borrow() {
+            if let Some(ctx) = ctx.as_ref() {
+                ctx.try_span_log(value);
             }
         }
-        Err(_err) => {}
-    };
+    });
+}
+
+pub(crate) fn start_span(id: &str) {
+    if let Some(obj) = observer().as_ref() {
+        obj.create_span(id);
+    }
 }
 
 pub(crate) fn end_span(is_critical: bool, err: Option<String>) {
-    match OBSERVER.as_ref().read() {
-        Ok(obj) => {
-            if let Some(obj) = obj.as_ref() {
-                obj.end_span(is_critical, err);
-            }
-        }
-        Err(_err) => {}
-    };
+    if let Some(obj) = observer().as_ref() {
+        obj.end_span(is_critical, err);
+    }
 }
 
 pub(crate) fn observe_field(key: &str, value: serde_json::Value) {
@@ -130,6 +155,15 @@ pub(crate) fn observe_result(result: impl serde::Serialize) {
     });
 }
 
+/// `observe_result`, if the span has no result yet.
+pub(crate) fn observe_default_result(result: impl serde::Serialize) {
+    CONTEXT.with(|ctx| {
+        if let Some(ctx) = ctx.borrow().as_ref() {
+            ctx.observe_span_default_result(result);
+        }
+    });
+}
+
 #[allow(dead_code)]
 pub fn observe_span_id(id: &str) {
     CONTEXT.with(|context| {
@@ -142,10 +176,11 @@ pub fn observe_span_id(id: &str) {
 impl Observer {
     /// Initialized Observer with different backends(NewRelic, StatsD, Sentry, Jaeger, etc...)
     /// and call their app started method
-
     pub fn builder(backend: Box<dyn Backend>) -> Self {
         Observer {
             backends: vec![backend],
+            errors: None,
+            slow_call: None,
         }
     }
 
@@ -154,17 +189,64 @@ impl Observer {
         self
     }
 
+    /// Send backend failures to `errors` instead of printing them to stderr.
+    pub fn with_error_channel(
+        mut self,
+        errors: std::sync::mpsc::SyncSender<errors::BackendError>,
+    ) -> Self {
+        self.errors = Some(errors);
+        self
+    }
+
+    /// Report backend calls taking longer than `threshold`. They are not interrupted.
+    pub fn with_slow_call_threshold(mut self, threshold: std::time::Duration) -> Self {
+        self.slow_call = Some(threshold);
+        self
+    }
+
     pub fn init(self) {
-        for backend in self.backends.iter() {
-            backend.app_started()
-        }
+        self.each("app_started", |backend| backend.app_started());
+
+        OBSERVER
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .replace(self);
+    }
 
-        match OBSERVER.write() {
-            Ok(mut obj) => {
-                obj.replace(self);
+    /// Calls every backend, catching and reporting errors and panics so one backend can not
+    /// keep the others from running.
+    fn each(&self, method: &'static str, call: impl Fn(&dyn Backend) -> Result<()>) {
+        for (index, backend) in self.backends.iter().enumerate() {
+            let started = std::time::Instant::now();
+            let outcome =
+                std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| call(backend.as_ref())));
+            let elapsed = started.elapsed();
+            let failure = match outcome {
+                Ok(Ok(())) => match self.slow_call {
+                    Some(threshold) if elapsed > threshold => Some((
+                        errors::ErrorKind::Slow,
+                        format!("took {}ms", elapsed.as_millis()),
+                    )),
+                    _ => None,
+                },
+                Ok(Err(e)) => Some((errors::ErrorKind::Failed, e.to_string())),
+                Err(panic) => Some((
+                    errors::ErrorKind::Panicked,
+                    errors::panic_message(panic.as_ref()),
+                )),
+            };
+            if let Some((kind, message)) = failure {
+                errors::report(
+                    self.errors.as_ref(),
+                    errors::BackendError {
+                        backend: index,
+                        method,
+                        kind,
+                        message,
+                    },
+                );
             }
-            Err(_e) => {}
-        };
+        }
     }
 
     /// It will iterate through all backends and call their context_created method.
@@ -174,9 +256,9 @@ impl Observer {
             if context.is_none() {
                 context.replace(Context::new(context_id.to_string()));
             }
-            for backend in self.backends.iter() {
-                backend.context_created(context_id);
-            }
+            self.each("context_created", |backend| {
+                backend.context_created(context_id)
+            });
         });
     }
 
@@ -186,9 +268,7 @@ impl Observer {
             let mut ctx = ctx.borrow_mut();
             if let Some(ctx) = ctx.as_ref() {
                 ctx.finalise();
-                for backend in self.backends.iter() {
-                    backend.context_ended(&ctx);
-                }
+                self.each("context_ended", |backend| backend.context_ended(ctx));
             }
             ctx.take();
         });
@@ -198,9 +278,7 @@ impl Observer {
         CONTEXT.with(|ctx| {
             if let Some(ctx) = ctx.borrow().as_ref() {
                 ctx.start_span(id);
-                for backend in self.backends.iter() {
-                    backend.span_created(id);
-                }
+                self.each("span_created", |backend| backend.span_created(id));
             }
         });
     }
@@ -209,9 +287,9 @@ impl Observer {
         CONTEXT.with(|ctx| {
             if let Some(ctx) = ctx.borrow().as_ref() {
                 ctx.end_span(is_critical, err);
-                for backend in self.backends.iter() {
-                    backend.span_ended(ctx.span_stack.borrow().last());
-                }
+                self.each("span_ended", |backend| {
+                    backend.span_ended(ctx.span_stack.borrow().last())
+                });
             }
         });
     }
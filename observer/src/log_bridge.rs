@@ -0,0 +1,87 @@
+//! `log` records as span logs.
+//!
+//! ```ignore
+//! observer::log_bridge::LogBridge::builder()
+//!     .with_level(log::LevelFilter::Debug)
+//!     .with_forward(Box::new(my_app_logger))
+//!     .init()
+//!     .expect("logger already set");
+//! ```
+
+/// `log::Log` appending every record emitted while a context is active on the thread to the
+/// logs of the current span, as `LEVEL target: message`, with the module path when it differs
+/// from the target.
+///
+/// Records are also passed on to the forward logger if there is one, with or without a context,
+/// so the application keeps its usual log output.
+pub struct LogBridge {
+    level: log::LevelFilter,
+    forward: Option<Box<dyn log::Log>>,
+}
+
+impl LogBridge {
+    pub fn builder() -> Self {
+        LogBridge {
+            level: log::LevelFilter::Info,
+            forward: None,
+        }
+    }
+
+    /// Most verbose level kept in span logs, defaults to `Info`.
+    pub fn with_level(mut self, level: log::LevelFilter) -> Self {
+        self.level = level;
+        self
+    }
+
+    pub fn with_forward(mut self, forward: Box<dyn log::Log>) -> Self {
+        self.forward = Some(forward);
+        self
+    }
+
+    /// Installs the bridge as the process wide logger, fails if one is already set.
+    pub fn init(self) -> std::result::Result<(), log::SetLoggerError> {
+        // the forward logger decides for itself, so let everything through to it
+        let max_level = if self.forward.is_some() {
+            log::LevelFilter::Trace
+        } else {
+            self.level
+        };
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+}
+
+impl log::Log for LogBridge {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.level
+            || matches!(&self.forward, Some(forward) if forward.enabled(metadata))
+    }
+
+    fn log(&self, record: &log::Record) {
+        if record.level() <= self.level {
+            let line = match record.module_path() {
+                Some(module) if module != record.target() => format!(
+                    "{} {} ({}): {}",
+                    record.level(),
+                    record.target(),
+                    module,
+                    record.args()
+                ),
+                _ => format!("{} {}: {}", record.level(), record.target(), record.args()),
+            };
+            crate::try_span_log(&line);
+        }
+        if let Some(forward) = &self.forward {
+            if forward.enabled(record.metadata()) {
+                forward.log(record);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(forward) = &self.forward {
+            forward.flush();
+        }
+    }
+}
@@ -25,6 +25,29 @@ impl Observe {
         }
     }
 
+    /// Like `observe_with_result`, also calling `summarize` with the `Ok` value, see
+    /// `resulty::Summarized`.
+    pub fn observe_with_summary<F, T, E, S>(
+        table_name: &str,
+        is_critical: bool,
+        run: F,
+        summarize: S,
+    ) -> Result<T, E>
+    where
+        F: FnOnce() -> Result<T, E>,
+        S: FnOnce(&T),
+        E: std::fmt::Debug,
+        Self: Sized,
+    {
+        Observe::observe_with_result(table_name, is_critical, || {
+            let result = run();
+            if let Ok(r) = &result {
+                summarize(r);
+            }
+            result
+        })
+    }
+
     pub fn observe_all<F, T>(table_name: &str, is_critical: bool, run: F) -> T
     where
         F: FnOnce() -> T,
@@ -62,6 +62,11 @@ pub fn observe_f32(name: &str, value: f32) {
     observe_field(name, json!(value));
 }
 
+/// Any serializable value, for fields of type `value` and of events not in the events file.
+pub fn observe_value<T: serde::Serialize>(name: &str, value: T) {
+    observe_field(name, json!(value));
+}
+
 pub fn observe_result_string(value: &str) {
     observe_result(json!(value))
 }
@@ -137,3 +142,128 @@ where
 {
     observe_result(json!(value));
 }
+
+pub fn observe_result_value<T: serde::Serialize>(value: T) {
+    observe_result(json!(value));
+}
+
+// The richer types of the events file. `#[observed]` turns the value into JSON with the
+// `typed_*` function of its type, nesting them for `Option<T>` and `list<T>`, and records it
+// with `observe_typed` or `observe_result_typed`. Values a type can not check at compile time
+// are validated here, problems are added to the logs of the span.
+
+pub fn observe_typed(name: &str, value: serde_json::Value) {
+    observe_field(name, value);
+}
+
+pub fn observe_result_typed(value: serde_json::Value) {
+    observe_result(value);
+}
+
+pub fn typed_primitive<T: serde::Serialize + ?Sized>(value: &T) -> serde_json::Value {
+    json!(value)
+}
+
+pub fn typed_null() -> serde_json::Value {
+    serde_json::Value::Null
+}
+
+pub fn typed_list(items: impl Iterator<Item = serde_json::Value>) -> serde_json::Value {
+    serde_json::Value::Array(items.collect())
+}
+
+/// RFC 3339, in the offset of the timestamp.
+pub fn typed_timestamp<Tz: chrono::TimeZone>(value: &chrono::DateTime<Tz>) -> serde_json::Value
+where
+    Tz::Offset: std::fmt::Display,
+{
+    json!(value.to_rfc3339())
+}
+
+/// Milliseconds.
+pub fn typed_duration(value: &std::time::Duration) -> serde_json::Value {
+    json!(value.as_secs_f64() * 1000.0)
+}
+
+pub fn typed_uuid(value: &uuid::Uuid) -> serde_json::Value {
+    json!(value.to_string())
+}
+
+pub fn typed_enum(name: &str, value: &str, allowed: &[&str]) -> serde_json::Value {
+    if !allowed.contains(&value) {
+        crate::try_span_log(&format!(
+            "field {}: expected one of {:?}, got {:?}",
+            name, allowed, value
+        ));
+    }
+    json!(value)
+}
+
+/// Schema of an object field, parsed the first time a value is checked against it.
+/// `#[observed]` keeps one in a static at every place it records an object.
+pub struct ObjectSchema {
+    /// JSON of the object field, as in the events file.
+    json: &'static str,
+    field: std::sync::OnceLock<Option<crate::schema::Field>>,
+}
+
+impl ObjectSchema {
+    pub const fn new(json: &'static str) -> Self {
+        ObjectSchema {
+            json,
+            field: std::sync::OnceLock::new(),
+        }
+    }
+
+    fn field(&self, name: &str) -> Option<&crate::schema::Field> {
+        self.field
+            .get_or_init(|| match serde_json::from_str(self.json) {
+                Ok(field) => Some(field),
+                Err(e) => {
+                    eprintln!("observer: invalid schema of field {}: {}", name, e);
+                    None
+                }
+            })
+            .as_ref()
+    }
+}
+
+pub fn typed_object<T: serde::Serialize + ?Sized>(
+    name: &str,
+    value: &T,
+    schema: &ObjectSchema,
+) -> serde_json::Value {
+    let value = json!(value);
+    if let Some(field) = schema.field(name) {
+        for problem in field.validate(&value) {
+            crate::try_span_log(&format!("field {}: {}", name, problem));
+        }
+    }
+    value
+}
+
+// Arguments `#[observed]` records without a declared type are serialized if they can be, else
+// recorded as their `Debug` output: `(&Captured(&value)).captured()` finds the `Serialize`
+// method first and only looks for the `Debug` one, behind one more reference, when that fails.
+
+pub struct Captured<'a, T: ?Sized>(pub &'a T);
+
+pub trait CapturedSerialize {
+    fn captured(&self) -> serde_json::Value;
+}
+
+impl<T: serde::Serialize + ?Sized> CapturedSerialize for Captured<'_, T> {
+    fn captured(&self) -> serde_json::Value {
+        json!(self.0)
+    }
+}
+
+pub trait CapturedDebug {
+    fn captured(&self) -> serde_json::Value;
+}
+
+impl<T: std::fmt::Debug + ?Sized> CapturedDebug for &Captured<'_, T> {
+    fn captured(&self) -> serde_json::Value {
+        json!(format!("{:?}", self.0))
+    }
+}
@@ -0,0 +1,8 @@
+/// Where spans of events marked `critical` in observer.json are sent by
+/// `backends::local_store::LocalStore`, e.g. a message broker or a database table.
+///
+/// `data` is the serialized span without its sub frames, with the key of its parent span as
+/// `parent`. Failures should be handled inside `enqueue`, there is nobody to return them to.
+pub trait Queue: Send + Sync {
+    fn enqueue(&self, data: serde_json::Value);
+}
@@ -1,138 +1,199 @@
-// TODO: Rough work
-
-//pub trait Resulty
-//where
-//    Self: std::fmt::Debug,
-//{
-//    fn to_string(&self) -> String {
-//        format!("{:?}", &self)
-//    }
-//}
-
-//pub trait Observable {
-//    fn observe(&self) -> serde_json::Value;
-//}
-//
-////impl<T> Observable for T where T: serde::Serialize {
-////    fn observe(&self)->  serde_json::Value {
-////        json!(self)
-////    }
-////}
-//
-//impl<T, E> Observable for Result<T, E>
-//    where T: Observable, E: Observable
-//{
-//    fn observe(&self) -> serde_json::Value {
-//        match &self {
-//            Ok(v) => json!({"ok": v.observe()}),
-//            Err(e) => json!({"err": e.observe()}),
-//        }
-//    }
-//}
-//
-//#[derive(Serialize)]
-//pub struct A {
-//    id: i32
-//}
-//
-//impl Observable for A {
-//    fn observe(&self) -> serde_json::Value {
-//        json!("")
-//    }
-//}
-
-//#[observed]
-//pub fn nth_prime(in_: In, n: i32) -> i32 {
-//    observe_field!(in_.ctx, "n", n);
-//    let p = {
-//        0
-//    };
-//    observe_result!(in_.ctx, p)
-//    // observe_i32_without_result()
-//}
-
-//#[observed_with_result]
-//pub fn create_user(in_: In, username: &str, age: i32) -> Result<User, String> {
-//    observe_field!(in_.ctx, "username", username);
-//    // events.json contains:
-//    // username: "string"
-//    // observe_string_field(in_.ctx, "username", username);
-//    observe_field!(in_.ctx, "age", age);
-//    // age: "i32"
-//    // observe_int_field(in_.ctx, "age", age);
-//
-//    observe_result!(in_.ctx, insert_user(username, age), |u| u.kind.to_string())
-//    // observed_result: "i32"
-//
-//    store_age(age)?;
-//
-//    let user = observe_i32_result(in_.ctx, insert_user(username, age), |u| (u.id, u.foo)?;
-//    //
-//    user
-//}
-//
-//fn observe_i32_result<T, E, F>(ctx: Context, r: Result<T, E>, f: F) -> Result<T, E>
-//where F: FnOnce(&T) -> i32
-//{
-//
-//}
-//
-//fn observe_i32_without_result<T, E, F>(ctx: Context, r: i32) -> i32
-//{
-//
-//}
-
-//pub fn atoi(ctx: Context, a: &str) -> i32 {
-//    let x = || {
-//        Ok(54)
-//    }();
-//    ctx.update(json!(x.observe()));
-//    x
-//}
-
-//pub trait Resulty<T, E> {
-//    fn o_success_i32<F>(self, ctx: &crate::Context, f: F) -> Result<T, E>
-//    where F: FnOnce(T) -> i32;
-//}
-//
-//
-//impl<T, E> Resulty<T, E> for Result<T, E> {
-//
-//    fn o_success_i32<F>(self, ctx: &crate::Context, f: F) -> Result<T, E>
-//    where
-//        F: FnOnce(&T) -> i32
-//    {
-//        match self {
-//            Ok(t) => {
-//                let t1 = f(&t);
-//                Ok(t)
-//            },
-//            Err(err) => {
-//                Err(err)
-//            }
-//        }
-//    }
-//}
-
-//pub trait ResultyI32 {
-//    fn o_success_i32(&self, ctx: &crate::Context, f: F) -> Result<E, T>
-//        where F: FnOnce(T) -> i32;
-//}
-//
-
-//impl Resulty for Result<E, T>
-//where E: std::fmt::Debug,
-//{
-//    fn o_success_i32(&self, ctx: &crate::Context, f: F) -> Result<E, T>
-//        where F: FnOnce(T) -> i32
-//    {
-//        match self {
-//            Ok(t) => {
-//                ctx.observe_success(f(t))
-//            },
-//            Err(e) => {
-//                ctx.observe_failure(e)
-//            }
-//        }
-//    }
-//}
+//! What `#[observed(with_result)]` records as the result of a span, when the function does not
+//! call `observe_result` itself: the summary of the value it returns in `Ok`, if its type
+//! implements `Resulty`. Values of other types, and of type parameters, are not recorded.
+//!
+//! `#[derive(Resulty)]` summarizes a struct by the fields marked `#[resulty]`, else by its `id`
+//! and `*_id` fields, else by all its fields, and an enum by the name of its variant:
+//!
+//! ```ignore
+//! #[derive(Resulty)]
+//! pub struct Policy {
+//!     id: String,
+//!     owner_id: i64,
+//!     body: String,
+//! }
+//! // {"id": "p1", "owner_id": 7}
+//! ```
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+pub type Summary = serde_json::Value;
+
+pub trait Resulty {
+    /// What identifies the value in the span, small enough to be stored with every span.
+    fn summary(&self) -> Summary;
+}
+
+// `(&Summarized(&value)).summarize()` finds the `Resulty` method first and only falls back to
+// recording nothing, behind one more reference, when the type does not implement it.
+
+pub struct Summarized<'a, T: ?Sized>(pub &'a T);
+
+pub trait SummarizedResulty {
+    fn summarize(&self);
+}
+
+impl<T: Resulty + ?Sized> SummarizedResulty for Summarized<'_, T> {
+    /// Records the summary, unless something the function called recorded a result already.
+    fn summarize(&self) {
+        crate::observe_default_result(self.0.summary());
+    }
+}
+
+pub trait SummarizedOther {
+    fn summarize(&self);
+}
+
+impl<T: ?Sized> SummarizedOther for &Summarized<'_, T> {
+    fn summarize(&self) {}
+}
+
+/// The summary of a struct, used by `#[derive(Resulty)]`.
+pub fn object(fields: Vec<(&str, Summary)>) -> Summary {
+    Summary::Object(
+        fields
+            .into_iter()
+            .map(|(name, summary)| (name.to_string(), summary))
+            .collect(),
+    )
+}
+
+macro_rules! serialized {
+    ($($ty:ty),*) => {
+        $(
+            impl Resulty for $ty {
+                fn summary(&self) -> Summary {
+                    json!(self)
+                }
+            }
+        )*
+    };
+}
+
+serialized!(
+    bool,
+    char,
+    i8,
+    i16,
+    i32,
+    i64,
+    isize,
+    u8,
+    u16,
+    u32,
+    u64,
+    usize,
+    f32,
+    f64,
+    str,
+    String,
+    ()
+);
+
+impl Resulty for Summary {
+    fn summary(&self) -> Summary {
+        self.clone()
+    }
+}
+
+impl<T: Resulty + ?Sized> Resulty for &T {
+    fn summary(&self) -> Summary {
+        (**self).summary()
+    }
+}
+
+impl<T: Resulty + ?Sized> Resulty for Box<T> {
+    fn summary(&self) -> Summary {
+        (**self).summary()
+    }
+}
+
+impl<T: Resulty + ?Sized> Resulty for std::sync::Arc<T> {
+    fn summary(&self) -> Summary {
+        (**self).summary()
+    }
+}
+
+impl<T: Resulty> Resulty for Option<T> {
+    fn summary(&self) -> Summary {
+        match self {
+            Some(value) => value.summary(),
+            None => Summary::Null,
+        }
+    }
+}
+
+impl<T: Resulty, E: Resulty> Resulty for Result<T, E> {
+    fn summary(&self) -> Summary {
+        match self {
+            Ok(value) => json!({ "ok": value.summary() }),
+            Err(e) => json!({ "err": e.summary() }),
+        }
+    }
+}
+
+impl<T: Resulty> Resulty for [T] {
+    fn summary(&self) -> Summary {
+        Summary::Array(self.iter().map(Resulty::summary).collect())
+    }
+}
+
+impl<T: Resulty> Resulty for Vec<T> {
+    fn summary(&self) -> Summary {
+        self.as_slice().summary()
+    }
+}
+
+impl<T: Resulty> Resulty for HashSet<T> {
+    fn summary(&self) -> Summary {
+        Summary::Array(self.iter().map(Resulty::summary).collect())
+    }
+}
+
+impl<T: Resulty> Resulty for BTreeSet<T> {
+    fn summary(&self) -> Summary {
+        Summary::Array(self.iter().map(Resulty::summary).collect())
+    }
+}
+
+impl<K: ToString, V: Resulty> Resulty for HashMap<K, V> {
+    fn summary(&self) -> Summary {
+        Summary::Object(
+            self.iter()
+                .map(|(k, v)| (k.to_string(), v.summary()))
+                .collect(),
+        )
+    }
+}
+
+impl<K: ToString, V: Resulty> Resulty for BTreeMap<K, V> {
+    fn summary(&self) -> Summary {
+        Summary::Object(
+            self.iter()
+                .map(|(k, v)| (k.to_string(), v.summary()))
+                .collect(),
+        )
+    }
+}
+
+// as the `timestamp`, `duration` and `uuid` types of the events file
+
+impl<Tz: chrono::TimeZone> Resulty for chrono::DateTime<Tz>
+where
+    Tz::Offset: std::fmt::Display,
+{
+    fn summary(&self) -> Summary {
+        crate::observe_fields::typed_timestamp(self)
+    }
+}
+
+impl Resulty for std::time::Duration {
+    fn summary(&self) -> Summary {
+        crate::observe_fields::typed_duration(self)
+    }
+}
+
+impl Resulty for uuid::Uuid {
+    fn summary(&self) -> Summary {
+        crate::observe_fields::typed_uuid(self)
+    }
+}
@@ -0,0 +1,344 @@
+//! The events files read at runtime, and the table each event maps to.
+use std::collections::HashMap;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Event {
+    pub critical: bool,
+    pub result_type: String,
+    pub fields: HashMap<String, Field>,
+}
+
+/// A field of an event, in observer.json either its type, e.g. `"i32"`, or an object with the
+/// type and a `required` or `optional` marker, e.g. `{"type": "i32", "optional": true}`.
+///
+/// `#[observed]` errors for required fields the function never observes and warns for unmarked
+/// ones.
+///
+/// Besides the primitive types a field can be a `timestamp` (RFC 3339 string), `duration`
+/// (milliseconds), `uuid`, `Option<T>`, `list<T>`, an `object` whose own `fields` are given the
+/// same way, or an `enum` of the strings given as `values`. `fields` and `values` belong to the
+/// innermost object or enum, e.g. of `{"type": "list<enum>", "values": ["a", "b"]}`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(from = "FieldSpec")]
+pub struct Field {
+    pub ty: String,
+    pub presence: Presence,
+    pub fields: HashMap<String, Field>,
+    pub values: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Presence {
+    Required,
+    Unmarked,
+    Optional,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FieldSpec {
+    Type(String),
+    Marked {
+        #[serde(rename = "type")]
+        ty: String,
+        #[serde(default)]
+        required: bool,
+        #[serde(default)]
+        optional: bool,
+        #[serde(default)]
+        fields: HashMap<String, Field>,
+        #[serde(default)]
+        values: Vec<String>,
+    },
+}
+
+impl From<FieldSpec> for Field {
+    fn from(spec: FieldSpec) -> Field {
+        match spec {
+            FieldSpec::Type(ty) => Field {
+                ty,
+                presence: Presence::Unmarked,
+                fields: HashMap::new(),
+                values: vec![],
+            },
+            FieldSpec::Marked {
+                ty,
+                required,
+                optional,
+                fields,
+                values,
+            } => Field {
+                ty,
+                fields,
+                values,
+                presence: match (required, optional) {
+                    (true, _) => Presence::Required,
+                    (false, true) => Presence::Optional,
+                    (false, false) => Presence::Unmarked,
+                },
+            },
+        }
+    }
+}
+
+impl Field {
+    /// What is wrong with `value` as a value of this field, nothing when it is valid.
+    pub fn validate(&self, value: &serde_json::Value) -> Vec<String> {
+        let mut problems = vec![];
+        self.check(&normalize(&self.ty), value, "", &mut problems);
+        problems
+    }
+
+    /// `at` is the path to `value` inside the field, e.g. `address.lines[1]`.
+    fn check(&self, ty: &str, value: &serde_json::Value, at: &str, problems: &mut Vec<String>) {
+        if let Some(inner) = generic(ty, "option") {
+            if !value.is_null() {
+                self.check(inner, value, at, problems);
+            }
+            return;
+        }
+        if let Some(inner) = generic(ty, "list").or_else(|| generic(ty, "vec")) {
+            match value.as_array() {
+                Some(items) => {
+                    for (i, item) in items.iter().enumerate() {
+                        self.check(inner, item, &format!("{}[{}]", at, i), problems);
+                    }
+                }
+                None => problems.push(problem(at, "a list", value)),
+            }
+            return;
+        }
+        let valid = match ty {
+            "string" | "str" | "&str" | "char" => value.is_string(),
+            "bool" => value.is_boolean(),
+            "i8" | "i16" | "i32" | "i64" | "isize" => value.is_i64(),
+            "u8" | "u16" | "u32" | "u64" | "usize" => value.is_u64(),
+            "f32" | "f64" | "duration" => value.is_number(),
+            "timestamp" => matches!(value.as_str(), Some(s)
+                if chrono::DateTime::parse_from_rfc3339(s).is_ok()),
+            "uuid" => matches!(value.as_str(), Some(s) if uuid::Uuid::parse_str(s).is_ok()),
+            "enum" => matches!(value.as_str(), Some(s) if self.values.iter().any(|v| v == s)),
+            "list" => value.is_array(),
+            "object" | "map" => match value.as_object() {
+                Some(object) => {
+                    self.check_object(object, at, problems);
+                    true
+                }
+                None => false,
+            },
+            _ => true,
+        };
+        if !valid {
+            let expected = match ty {
+                "enum" => format!("one of {:?}", self.values),
+                "timestamp" => "an RFC 3339 timestamp".to_string(),
+                ty => ty.to_string(),
+            };
+            problems.push(problem(at, &expected, value));
+        }
+    }
+
+    fn check_object(
+        &self,
+        object: &serde_json::Map<String, serde_json::Value>,
+        at: &str,
+        problems: &mut Vec<String>,
+    ) {
+        if self.fields.is_empty() {
+            return;
+        }
+        let path = |name: &str| {
+            if at.is_empty() {
+                name.to_string()
+            } else {
+                format!("{}.{}", at, name)
+            }
+        };
+        let mut names: Vec<_> = self.fields.keys().collect();
+        names.sort();
+        for name in names {
+            let field = &self.fields[name];
+            match object.get(name) {
+                Some(value) => field.check(&normalize(&field.ty), value, &path(name), problems),
+                None if field.presence == Presence::Required => {
+                    problems.push(format!("{}: required but missing", path(name)))
+                }
+                None => {}
+            }
+        }
+        let mut unknown: Vec<_> = object
+            .keys()
+            .filter(|k| !self.fields.contains_key(*k))
+            .collect();
+        unknown.sort();
+        for name in unknown {
+            problems.push(format!("{}: not a field of the object", path(name)));
+        }
+    }
+}
+
+fn problem(at: &str, expected: &str, value: &serde_json::Value) -> String {
+    if at.is_empty() {
+        format!("expected {}, got {}", expected, value)
+    } else {
+        format!("{}: expected {}, got {}", at, expected, value)
+    }
+}
+
+/// Lowercase without whitespace, `Option<String>` becomes `option<string>`.
+fn normalize(ty: &str) -> String {
+    ty.chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// `T` of `name<T>`.
+fn generic<'a>(ty: &'a str, name: &str) -> Option<&'a str> {
+    ty.strip_prefix(name)?.strip_prefix('<')?.strip_suffix('>')
+}
+
+/// How a value of an observer.json type is stored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColumnType {
+    Integer,
+    Real,
+    Boolean,
+    Text,
+    Timestamp,
+    /// Lists, maps and anything else not covered above.
+    Json,
+}
+
+impl ColumnType {
+    pub fn from_event_type(ty: &str) -> ColumnType {
+        let ty = normalize(ty);
+        if let Some(inner) = generic(&ty, "option") {
+            return ColumnType::from_event_type(inner);
+        }
+        match ty.as_str() {
+            "i8" | "i16" | "i32" | "i64" | "isize" | "u8" | "u16" | "u32" | "u64" | "usize" => {
+                ColumnType::Integer
+            }
+            "f32" | "f64" | "duration" => ColumnType::Real,
+            "bool" => ColumnType::Boolean,
+            "string" | "str" | "&str" | "char" | "uuid" | "enum" => ColumnType::Text,
+            "timestamp" => ColumnType::Timestamp,
+            _ => ColumnType::Json,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Column {
+    pub name: String,
+    pub kind: ColumnType,
+}
+
+/// One row per finished span of the event: span bookkeeping followed by one `field_<name>`
+/// column per field, sorted by name.
+#[derive(Clone, Debug)]
+pub struct Table {
+    pub name: String,
+    pub columns: Vec<Column>,
+    /// observer.json field name of every column past the fixed ones, in column order.
+    pub fields: Vec<String>,
+}
+
+pub(crate) static FIXED_COLUMNS: &[(&str, ColumnType)] = &[
+    ("span_key", ColumnType::Text),
+    ("parent_key", ColumnType::Text),
+    ("context_id", ColumnType::Text),
+    ("context_key", ColumnType::Text),
+    ("start_time", ColumnType::Timestamp),
+    ("end_time", ColumnType::Timestamp),
+    ("duration_ms", ColumnType::Integer),
+    ("success", ColumnType::Boolean),
+    ("error", ColumnType::Text),
+];
+
+impl Table {
+    /// Fails if two fields of the event map to the same column, e.g. `a-b` and `a_b`.
+    pub fn for_event(name: &str, event: &Event) -> crate::Result<Table> {
+        let mut columns: Vec<Column> = FIXED_COLUMNS
+            .iter()
+            .map(|(name, kind)| Column {
+                name: name.to_string(),
+                kind: *kind,
+            })
+            .collect();
+        columns.push(Column {
+            name: "result".to_string(),
+            kind: ColumnType::from_event_type(&event.result_type),
+        });
+        let mut fields: Vec<_> = event.fields.iter().collect();
+        fields.sort_by(|a, b| a.0.cmp(b.0));
+        let mut columns_of: HashMap<String, &str> = HashMap::new();
+        for (field, spec) in fields.iter() {
+            let column = format!("field_{}", identifier(field));
+            if let Some(other) = columns_of.insert(column.clone(), field) {
+                return Err(failure::format_err!(
+                    "fields \"{}\" and \"{}\" of event \"{}\" both map to column {}",
+                    other,
+                    field,
+                    name,
+                    column
+                ));
+            }
+            columns.push(Column {
+                name: column,
+                kind: ColumnType::from_event_type(&spec.ty),
+            });
+        }
+        Ok(Table {
+            name: identifier(name),
+            columns,
+            fields: fields.into_iter().map(|(field, _)| field.clone()).collect(),
+        })
+    }
+}
+
+/// Events of the events file at `path` and of the files it includes, see `#[observed]` for the
+/// format. TOML and YAML files need the `toml` and `yaml` features.
+pub fn load(path: &str) -> crate::Result<HashMap<String, Event>> {
+    observer_events::merge(&[std::path::PathBuf::from(path)])
+        .map_err(|e| failure::format_err!("{}", e))?
+        .into_iter()
+        .map(|(name, (event, path))| {
+            serde_json::from_value(event)
+                .map(|event| (name.clone(), event))
+                .map_err(|e| {
+                    failure::format_err!("Event \"{}\" of {}: {}", name, path.display(), e)
+                })
+        })
+        .collect()
+}
+
+/// A table for every event of the events file at `path`, keyed by event name. Fails if two
+/// events map to the same table, e.g. `a-b` and `a_b`.
+pub fn tables(path: &str) -> crate::Result<HashMap<String, Table>> {
+    let mut events: Vec<_> = load(path)?.into_iter().collect();
+    events.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut tables: HashMap<String, Table> = HashMap::new();
+    let mut events_of: HashMap<String, String> = HashMap::new();
+    for (name, event) in events {
+        let table = Table::for_event(&name, &event)?;
+        if let Some(other) = events_of.insert(table.name.clone(), name.clone()) {
+            return Err(failure::format_err!(
+                "events \"{}\" and \"{}\" both map to table {}",
+                other,
+                name,
+                table.name
+            ));
+        }
+        tables.insert(name, table);
+    }
+    Ok(tables)
+}
+
+/// Only ASCII letters, digits and `_`, so names can go into SQL unescaped.
+fn identifier(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
@@ -14,6 +14,9 @@ pub struct Span {
     pub start_time: DateTime<Utc>,
     pub end_time: Option<DateTime<Utc>>,
     pub sub_frames: Vec<Span>,
+    /// Whether the event is marked `critical` in observer.json.
+    #[serde(default)]
+    pub critical: bool,
 }
 
 impl Clone for Span {
@@ -35,6 +38,7 @@ impl Debug for Span {
             .field("start_time", &self.start_time)
             .field("end_time", &self.end_time)
             .field("sub_frames", &self.sub_frames)
+            .field("critical", &self.critical)
             .finish()
     }
 }
@@ -52,8 +56,31 @@ impl Span {
             start_time: Utc::now(),
             end_time: None,
             sub_frames: vec![],
+            critical: false,
         }
     }
+    /// Deep copy of the span and its sub frames, `clone` only keeps the id.
+    pub(crate) fn snapshot(&self) -> Span {
+        self.with_sub_frames(self.sub_frames.iter().map(Span::snapshot).collect())
+    }
+
+    /// Copy of the span with `sub_frames` instead of its own.
+    pub(crate) fn with_sub_frames(&self, sub_frames: Vec<Span>) -> Span {
+        Span {
+            id: self.id.clone(),
+            key: self.key.clone(),
+            breadcrumbs: self.breadcrumbs.clone(),
+            success: self.success,
+            result: self.result.clone(),
+            err: self.err.clone(),
+            logs: self.logs.clone(),
+            start_time: self.start_time,
+            end_time: self.end_time,
+            sub_frames,
+            critical: self.critical,
+        }
+    }
+
     pub(crate) fn set_id(&mut self, id: &str) {
         self.id = id.to_string();
     }
@@ -78,6 +105,11 @@ impl Span {
         self
     }
 
+    pub fn set_critical(&mut self, critical: bool) -> &mut Self {
+        self.critical = critical;
+        self
+    }
+
     pub fn set_err(&mut self, err: Option<String>) -> &mut Self {
         self.err = err;
         self
@@ -87,6 +119,22 @@ impl Span {
         self.sub_frames.push(frame);
     }
 
+    /// Time from start to end, or to now while the span is running.
+    pub fn duration(&self) -> chrono::Duration {
+        self.end_time
+            .unwrap_or_else(Utc::now)
+            .signed_duration_since(self.start_time)
+    }
+
+    pub fn duration_ms(&self) -> i64 {
+        self.duration().num_milliseconds()
+    }
+
+    /// Saturates for spans longer than `i64::MAX` microseconds.
+    pub fn duration_micros(&self) -> i64 {
+        self.duration().num_microseconds().unwrap_or(i64::MAX)
+    }
+
     pub fn get_key(&self) -> String {
         self.key.clone()
     }
@@ -0,0 +1,309 @@
+//! Helpers for testing what code observes.
+//!
+//! ```ignore
+//! let (_, ctx) = observer::testing::observe("test", || create_temp("temp"));
+//! observer::testing::assert_span_under(&ctx, "update_temp", "foo__create_temp");
+//! observer::testing::assert_field(&ctx, "update_temp", "id", "temp");
+//! ```
+use crate::span::Span;
+use crate::Context;
+use std::sync::{Arc, Mutex, Once};
+use std::thread::ThreadId;
+
+type Store = Arc<Mutex<Vec<(ThreadId, Context)>>>;
+
+lazy_static! {
+    static ref STORE: Store = Arc::new(Mutex::new(vec![]));
+}
+
+static SPACE: usize = 4;
+
+/// Backend keeping a copy of every finished context in memory.
+pub struct Capture {
+    contexts: Store,
+}
+
+impl Capture {
+    pub fn new() -> Self {
+        Capture {
+            contexts: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Handle to the captured contexts, usable after the backend is moved into the observer.
+    pub fn contexts(&self) -> Captured {
+        Captured {
+            contexts: self.contexts.clone(),
+        }
+    }
+}
+
+impl Default for Capture {
+    fn default() -> Self {
+        Capture::new()
+    }
+}
+
+impl crate::Backend for Capture {
+    fn app_started(&self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn app_ended(&self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn context_created(&self, _id: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn context_ended(&self, ctx: &Context) -> crate::Result<()> {
+        if let Ok(mut contexts) = self.contexts.lock() {
+            contexts.push((std::thread::current().id(), ctx.snapshot()));
+        }
+        Ok(())
+    }
+
+    fn span_created(&self, _id: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn span_data(&self, _key: &str, _value: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn span_ended(&self, _span: Option<&Span>) -> crate::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct Captured {
+    contexts: Store,
+}
+
+impl Captured {
+    /// Removes and returns the contexts that finished on the current thread, oldest first.
+    ///
+    /// Tests run on their own threads, so this keeps parallel tests from seeing each other.
+    pub fn take(&self) -> Vec<Context> {
+        let current = std::thread::current().id();
+        let mut taken = vec![];
+        if let Ok(mut contexts) = self.contexts.lock() {
+            let mut rest = vec![];
+            for (thread, ctx) in contexts.drain(..) {
+                if thread == current {
+                    taken.push(ctx);
+                } else {
+                    rest.push((thread, ctx));
+                }
+            }
+            *contexts = rest;
+        }
+        taken
+    }
+
+    /// Removes and returns the contexts finished on any thread, oldest first.
+    pub fn take_all(&self) -> Vec<Context> {
+        match self.contexts.lock() {
+            Ok(mut contexts) => contexts.drain(..).map(|(_, ctx)| ctx).collect(),
+            Err(_err) => vec![],
+        }
+    }
+}
+
+/// Installs, once per process, an observer whose only backend is a `Capture`.
+///
+/// Any later `Observer::init` replaces it, so tests using this should not build their own.
+pub fn init() -> Captured {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        crate::builder(Box::new(Capture {
+            contexts: STORE.clone(),
+        }))
+        .init();
+    });
+    Captured {
+        contexts: STORE.clone(),
+    }
+}
+
+/// Runs `f` in a new context with `id` and returns its result with the finished context.
+///
+/// Must not be called while a context is already active on the current thread.
+pub fn observe<F, T>(id: &str, f: F) -> (T, Context)
+where
+    F: FnOnce() -> T,
+{
+    let captured = init();
+    crate::create_context(id);
+    let result = f();
+    crate::end_context();
+    let ctx = captured
+        .take()
+        .into_iter()
+        .rev()
+        .find(|ctx| ctx.id() == id)
+        .expect("context was not captured, was the observer replaced?");
+    (result, ctx)
+}
+
+fn walk<'a>(spans: &'a [Span], ancestors: &mut Vec<&'a str>, f: &mut dyn FnMut(&'a Span, &[&str])) {
+    for span in spans.iter() {
+        f(span, ancestors);
+        ancestors.push(&span.id);
+        walk(&span.sub_frames, ancestors, f);
+        ancestors.pop();
+    }
+}
+
+fn visit(ctx: &Context, f: &mut dyn FnMut(&Span, &[&str])) {
+    let frame = ctx.span_stack.borrow();
+    if let Some(frame) = frame.first() {
+        walk(&frame.sub_frames, &mut vec![], f);
+    }
+}
+
+/// Copies of every span with `id`, in the order they started.
+pub fn find_spans(ctx: &Context, id: &str) -> Vec<Span> {
+    let mut spans = vec![];
+    visit(ctx, &mut |span, _| {
+        if span.id == id {
+            spans.push(span.snapshot());
+        }
+    });
+    spans
+}
+
+pub fn has_span(ctx: &Context, id: &str) -> bool {
+    !find_spans(ctx, id).is_empty()
+}
+
+/// Whether a span `id` exists anywhere below a span `ancestor`.
+pub fn has_span_under(ctx: &Context, id: &str, ancestor: &str) -> bool {
+    let mut found = false;
+    visit(ctx, &mut |span, ancestors| {
+        found = found || (span.id == id && ancestors.contains(&ancestor));
+    });
+    found
+}
+
+/// Value of breadcrumb `key` on the first span `span_id` that has it.
+pub fn field(ctx: &Context, span_id: &str, key: &str) -> Option<serde_json::Value> {
+    find_spans(ctx, span_id)
+        .into_iter()
+        .find_map(|span| span.breadcrumbs.get(key).cloned())
+}
+
+pub fn assert_span(ctx: &Context, id: &str) {
+    if !has_span(ctx, id) {
+        panic!("no span `{}` in\n{}", id, snapshot(ctx));
+    }
+}
+
+pub fn assert_span_under(ctx: &Context, id: &str, ancestor: &str) {
+    if !has_span_under(ctx, id, ancestor) {
+        panic!(
+            "no span `{}` under `{}` in\n{}",
+            id,
+            ancestor,
+            snapshot(ctx)
+        );
+    }
+}
+
+/// Asserts some span `span_id` observed `key` with a value equal to `expected`.
+pub fn assert_field(ctx: &Context, span_id: &str, key: &str, expected: impl serde::Serialize) {
+    let expected = json!(expected);
+    let found = find_spans(ctx, span_id)
+        .iter()
+        .any(|span| span.breadcrumbs.get(key) == Some(&expected));
+    if !found {
+        panic!(
+            "no span `{}` with @{}: {} in\n{}",
+            span_id,
+            key,
+            expected,
+            snapshot(ctx)
+        );
+    }
+}
+
+/// Asserts some span `span_id` failed with an error containing `pattern`.
+pub fn assert_failed(ctx: &Context, span_id: &str, pattern: &str) {
+    let found = find_spans(ctx, span_id).iter().any(|span| {
+        span.success == Some(false) && matches!(&span.err, Some(e) if e.contains(pattern))
+    });
+    if !found {
+        panic!(
+            "no span `{}` failing with `{}` in\n{}",
+            span_id,
+            pattern,
+            snapshot(ctx)
+        );
+    }
+}
+
+/// The context tree in the `Logger` layout without timings and with sorted breadcrumbs, so it
+/// can be compared against an expected string.
+pub fn snapshot(ctx: &Context) -> String {
+    let mut writer = format!("context: {}\n", ctx.id());
+    let frame = ctx.span_stack.borrow();
+    if let Some(frame) = frame.first() {
+        snapshot_spans(&mut writer, &frame.sub_frames, SPACE);
+    }
+    writer
+}
+
+fn snapshot_spans(writer: &mut String, spans: &[Span], space: usize) {
+    for span in spans.iter() {
+        writer.push_str(&format!("{:>space$}{}\n", "", span.id, space = space));
+        let mut breadcrumbs: Vec<_> = span.breadcrumbs.iter().collect();
+        breadcrumbs.sort_by(|a, b| a.0.cmp(b.0));
+        for (key, value) in breadcrumbs {
+            writer.push_str(&format!(
+                "{:>space$}@{}: {}\n",
+                "",
+                key,
+                value,
+                space = space + SPACE
+            ));
+        }
+        if let Some(success) = span.success {
+            writer.push_str(&format!(
+                "{:>space$}@@success: {}\n",
+                "",
+                success,
+                space = space + SPACE
+            ));
+        }
+        if let Some(result) = &span.result {
+            writer.push_str(&format!(
+                "{:>space$}#result: {}\n",
+                "",
+                result,
+                space = space + SPACE
+            ));
+        }
+        if let Some(err) = &span.err {
+            writer.push_str(&format!(
+                "{:>space$}#error: {}\n",
+                "",
+                err,
+                space = space + SPACE
+            ));
+        }
+        if !span.logs.is_empty() {
+            writer.push_str(&format!("{:>space$}logs:\n", "", space = space + SPACE));
+            for log in span.logs.iter() {
+                writer.push_str(&format!(
+                    "{:>space$} - {}\n",
+                    "",
+                    log.1,
+                    space = space + SPACE + 2
+                ));
+            }
+        }
+        snapshot_spans(writer, &span.sub_frames, space + SPACE);
+    }
+}
@@ -37,3 +37,1074 @@
 //        thread::sleep(Duration::from_millis(500));
 //    }
 //}
+
+fn create_temp(id: &str) -> Result<i32, String> {
+    crate::observe::Observe::observe_all("foo__create_temp", false, || {
+        crate::observe_field("id", json!(id));
+        crate::observe_span_log("creating temp");
+        update_temp(id)
+    })
+}
+
+fn update_temp(id: &str) -> Result<i32, String> {
+    crate::observe::Observe::observe_with_result("update_temp", false, || {
+        crate::observe_field("id", json!(id));
+        if id.is_empty() {
+            return Err("empty id".to_string());
+        }
+        crate::observe_result(2314);
+        Ok(2314)
+    })
+}
+
+#[test]
+fn testing_captures_context() {
+    let (result, ctx) = crate::testing::observe("test", || create_temp("temp"));
+    assert_eq!(result, Ok(2314));
+    crate::testing::assert_span(&ctx, "foo__create_temp");
+    crate::testing::assert_span_under(&ctx, "update_temp", "foo__create_temp");
+    crate::testing::assert_field(&ctx, "update_temp", "id", "temp");
+    assert!(!crate::testing::has_span_under(
+        &ctx,
+        "foo__create_temp",
+        "update_temp"
+    ));
+    assert_eq!(
+        crate::testing::snapshot(&ctx),
+        r#"context: test
+    foo__create_temp
+        @id: "temp"
+        @@success: true
+        logs:
+           - creating temp
+        update_temp
+            @id: "temp"
+            @@success: true
+            #result: 2314
+"#
+    );
+}
+
+#[test]
+fn testing_captures_failure() {
+    let (_, ctx) = crate::testing::observe("failing", || create_temp(""));
+    crate::testing::assert_failed(&ctx, "update_temp", "empty id");
+    assert_eq!(
+        crate::testing::field(&ctx, "update_temp", "id"),
+        Some(json!(""))
+    );
+}
+
+/// Counts `app_started` calls, panics or fails in them when asked to.
+struct Probe {
+    calls: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    panics: bool,
+    fails: bool,
+}
+
+impl Probe {
+    fn new(
+        panics: bool,
+        fails: bool,
+    ) -> (Box<Self>, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+        let calls = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let probe = Probe {
+            calls: calls.clone(),
+            panics,
+            fails,
+        };
+        (Box::new(probe), calls)
+    }
+}
+
+impl crate::Backend for Probe {
+    fn app_started(&self) -> crate::Result<()> {
+        self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        if self.panics {
+            panic!("probe panicked");
+        }
+        if self.fails {
+            return Err(failure::format_err!("probe failed"));
+        }
+        Ok(())
+    }
+
+    fn app_ended(&self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn context_created(&self, _id: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn context_ended(&self, _ctx: &crate::Context) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn span_created(&self, _id: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn span_data(&self, _key: &str, _value: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn span_ended(&self, _span: Option<&crate::span::Span>) -> crate::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn backend_failures_are_isolated_and_reported() {
+    let (panicking, panicking_calls) = Probe::new(true, false);
+    let (failing, failing_calls) = Probe::new(false, true);
+    let (working, working_calls) = Probe::new(false, false);
+    let (errors, received) = std::sync::mpsc::sync_channel(10);
+    let observer = crate::Observer::builder(panicking)
+        .add_backend(failing)
+        .add_backend(working)
+        .with_error_channel(errors);
+    let before = crate::errors::error_count();
+
+    observer.each("app_started", |backend| backend.app_started());
+
+    for calls in [panicking_calls, failing_calls, working_calls] {
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+    let reported: Vec<_> = received.try_iter().collect();
+    assert_eq!(reported.len(), 2);
+    assert_eq!(
+        (reported[0].backend, reported[0].method, reported[0].kind),
+        (0, "app_started", crate::errors::ErrorKind::Panicked)
+    );
+    assert_eq!(reported[0].message, "probe panicked");
+    assert_eq!(
+        (reported[1].backend, reported[1].kind),
+        (1, crate::errors::ErrorKind::Failed)
+    );
+    assert_eq!(reported[1].message, "probe failed");
+    assert!(crate::errors::error_count() >= before + 2);
+}
+
+#[test]
+fn slow_backend_calls_are_reported() {
+    let (probe, _) = Probe::new(false, false);
+    let (errors, received) = std::sync::mpsc::sync_channel(10);
+    let observer = crate::Observer::builder(probe)
+        .with_error_channel(errors)
+        .with_slow_call_threshold(std::time::Duration::from_millis(1));
+
+    observer.each("app_started", |_| {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        Ok(())
+    });
+
+    let reported = received.try_recv().expect("slow call was not reported");
+    assert_eq!(reported.kind, crate::errors::ErrorKind::Slow);
+}
+
+#[test]
+fn full_error_channel_never_blocks() {
+    let (panicking, _) = Probe::new(true, false);
+    let (errors, received) = std::sync::mpsc::sync_channel(1);
+    let observer = crate::Observer::builder(panicking).with_error_channel(errors);
+
+    for _ in 0..3 {
+        observer.each("app_started", |backend| backend.app_started());
+    }
+
+    assert_eq!(received.try_iter().count(), 1);
+}
+
+#[test]
+fn poisoned_observer_lock_is_recovered() {
+    let poisoner = std::thread::spawn(|| {
+        let _guard = crate::OBSERVER.write().unwrap();
+        panic!("poisoning the observer lock");
+    });
+    assert!(poisoner.join().is_err());
+    assert!(crate::OBSERVER.is_poisoned());
+
+    // the helpers still work, and contexts are still captured
+    let (result, ctx) = crate::testing::observe("after_poison", || create_temp("temp"));
+    assert_eq!(result, Ok(2314));
+    crate::testing::assert_span(&ctx, "update_temp");
+}
+
+/// Keeps a copy of every context that ends.
+struct Ended(std::sync::Arc<std::sync::Mutex<Vec<crate::Context>>>);
+
+impl Ended {
+    fn new() -> (
+        Box<Self>,
+        std::sync::Arc<std::sync::Mutex<Vec<crate::Context>>>,
+    ) {
+        let ended = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        (Box::new(Ended(ended.clone())), ended)
+    }
+}
+
+impl crate::Backend for Ended {
+    fn app_started(&self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn app_ended(&self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn context_created(&self, _id: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn context_ended(&self, ctx: &crate::Context) -> crate::Result<()> {
+        self.0.lock().unwrap().push(ctx.snapshot());
+        Ok(())
+    }
+
+    fn span_created(&self, _id: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn span_data(&self, _key: &str, _value: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn span_ended(&self, _span: Option<&crate::span::Span>) -> crate::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn filter_ends_every_context_it_created() {
+    use crate::Backend;
+    let (inner, ended) = Ended::new();
+    let filter = crate::backends::middleware::Filter::builder(inner)
+        .with_span("kept")
+        .build();
+    let ctx = crate::Context::new("filtered".to_string());
+    filter.context_created(&ctx.id()).unwrap();
+    ctx.start_span("dropped");
+    ctx.end_span(false, None);
+    ctx.finalise();
+    filter.context_ended(&ctx).unwrap();
+
+    let ended = ended.lock().unwrap();
+    assert_eq!(ended.len(), 1);
+    assert!(ended[0].span_stack.borrow()[0].sub_frames.is_empty());
+}
+
+#[test]
+fn redact_cleans_result_error_and_logs() {
+    use crate::Backend;
+    let (inner, ended) = Ended::new();
+    let redact = crate::backends::middleware::Redact::builder(inner)
+        .with_redacted("password")
+        .with_dropped("token")
+        .build();
+    let ctx = crate::Context::new("redacted".to_string());
+    ctx.start_span("login");
+    ctx.observe_span_field("password", json!("hunter2"));
+    ctx.observe_span_result(json!({
+        "user": "alice",
+        "password": "hunter2",
+        "token": "abc",
+        "attempts": [{"password": "old"}, "was hunter2"],
+    }));
+    ctx.span_log("checking hunter2");
+    ctx.end_span(false, Some("wrong password hunter2".to_string()));
+    ctx.finalise();
+    redact.context_ended(&ctx).unwrap();
+
+    let ended = ended.lock().unwrap();
+    let stack = ended[0].span_stack.borrow();
+    let span = &stack[0].sub_frames[0];
+    assert_eq!(span.breadcrumbs["password"], json!("[REDACTED]"));
+    assert_eq!(
+        span.result,
+        Some(json!({
+            "user": "alice",
+            "password": "[REDACTED]",
+            "attempts": [{"password": "[REDACTED]"}, "was [REDACTED]"],
+        }))
+    );
+    assert_eq!(span.err.as_deref(), Some("wrong password [REDACTED]"));
+    assert_eq!(span.logs[0].1, "checking [REDACTED]");
+}
+
+#[test]
+fn syslog_context_messages_carry_spans_and_breadcrumbs() {
+    use crate::Backend;
+    let daemon = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    daemon
+        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .unwrap();
+    let syslog = crate::backends::syslog::Syslog::builder()
+        .with_udp(&daemon.local_addr().unwrap().to_string())
+        .build()
+        .unwrap();
+    let ctx = crate::Context::new("checkout".to_string());
+    ctx.start_span("pay");
+    ctx.observe_span_field("amount", json!(7));
+    ctx.start_span("charge");
+    ctx.end_span(false, None);
+    ctx.end_span(false, None);
+    ctx.finalise();
+    syslog.context_ended(&ctx).unwrap();
+
+    let mut buffer = [0; 4096];
+    let received = daemon.recv(&mut buffer).unwrap();
+    let message = String::from_utf8_lossy(&buffer[..received]);
+    assert!(
+        message.contains(
+            r#"[spans@32473 span_id="pay" span_id="charge"][breadcrumbs@32473 pay.amount="7"]"#
+        ),
+        "{}",
+        message
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn syslog_reports_an_unreachable_daemon() {
+    use crate::Backend;
+    let path = std::env::temp_dir().join(format!("observer-{}.sock", uuid::Uuid::new_v4()));
+    let error = crate::backends::syslog::Syslog::builder()
+        .with_unix_socket(path.to_str().unwrap())
+        .build()
+        .err()
+        .unwrap();
+    assert!(
+        error
+            .to_string()
+            .starts_with("Failed to connect to syslog: "),
+        "{}",
+        error
+    );
+
+    let daemon = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+    let syslog = crate::backends::syslog::Syslog::builder()
+        .with_unix_socket(path.to_str().unwrap())
+        .per_span()
+        .build()
+        .unwrap();
+    drop(daemon);
+    std::fs::remove_file(&path).unwrap();
+    let ctx = crate::Context::new("checkout".to_string());
+    ctx.start_span("pay");
+    ctx.end_span(false, None);
+    ctx.finalise();
+    assert!(syslog.context_ended(&ctx).is_err());
+}
+
+/// Keeps what is enqueued.
+struct Enqueued(std::sync::Arc<std::sync::Mutex<Vec<serde_json::Value>>>);
+
+impl crate::queue::Queue for Enqueued {
+    fn enqueue(&self, data: serde_json::Value) {
+        self.0.lock().unwrap().push(data);
+    }
+}
+
+#[test]
+fn local_store_keeps_every_span_once_inside_its_directory() {
+    use crate::Backend;
+    let directory = std::env::temp_dir().join(format!("observer-{}", uuid::Uuid::new_v4()));
+    let enqueued = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+    let store = crate::backends::local_store::LocalStore::builder(directory.to_str().unwrap())
+        .with_queue(Box::new(Enqueued(enqueued.clone())))
+        .build();
+    let ctx = crate::Context::new("stored".to_string());
+    ctx.start_span("pay");
+    ctx.start_span("..");
+    ctx.end_span(false, None);
+    ctx.start_span(".");
+    ctx.end_span(false, None);
+    ctx.end_span(true, None);
+    ctx.finalise();
+    store.context_ended(&ctx).unwrap();
+
+    let enqueued = enqueued.lock().unwrap();
+    assert_eq!(enqueued.len(), 1);
+    assert_eq!(enqueued[0]["id"], json!("pay"));
+    assert_eq!(enqueued[0]["sub_frames"], json!([]));
+    let pay_key = enqueued[0]["key"].clone();
+
+    let mut stored: Vec<_> = std::fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    stored.sort();
+    assert_eq!(stored, ["%2E", "%2E%2E"]);
+    for event in stored {
+        let files: Vec<_> = std::fs::read_dir(directory.join(event))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        let data: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&files[0]).unwrap()).unwrap();
+        assert_eq!(data["parent"], pay_key);
+    }
+    let _ = std::fs::remove_dir_all(&directory);
+}
+
+/// Writes `events` to a fresh events file.
+fn events_file(events: serde_json::Value) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("observer-{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(&path, events.to_string()).unwrap();
+    path
+}
+
+#[test]
+fn colliding_columns_and_tables_are_rejected() {
+    let fields = events_file(json!({
+        "pay": {"critical": false, "result_type": "i32", "fields": {"a-b": "i32", "a_b": "i32"}}
+    }));
+    let tables = events_file(json!({
+        "pay-later": {"critical": false, "result_type": "i32", "fields": {}},
+        "pay_later": {"critical": false, "result_type": "i32", "fields": {}}
+    }));
+    let fields_error = crate::schema::tables(fields.to_str().unwrap())
+        .unwrap_err()
+        .to_string();
+    let tables_error = crate::schema::tables(tables.to_str().unwrap())
+        .unwrap_err()
+        .to_string();
+    let _ = std::fs::remove_file(&fields);
+    let _ = std::fs::remove_file(&tables);
+    assert_eq!(
+        fields_error,
+        r#"fields "a-b" and "a_b" of event "pay" both map to column field_a_b"#
+    );
+    assert_eq!(
+        tables_error,
+        r#"events "pay-later" and "pay_later" both map to table pay_later"#
+    );
+}
+
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+#[test]
+fn sql_build_reports_instead_of_panicking() {
+    let error = crate::backends::sql::Sql::builder("/nonexistent/observer.json")
+        .build()
+        .err()
+        .unwrap();
+    assert!(
+        error.to_string().starts_with("Not able to load"),
+        "{}",
+        error
+    );
+
+    let events = events_file(json!({}));
+    let error = crate::backends::sql::Sql::builder(events.to_str().unwrap())
+        .build()
+        .err()
+        .unwrap();
+    let _ = std::fs::remove_file(&events);
+    assert_eq!(error.to_string(), "No database given");
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn sql_stores_mismatched_values_as_null_and_reports_them() {
+    use crate::Backend;
+    let events = events_file(json!({
+        "pay": {"critical": false, "result_type": "i32", "fields": {"amount": "i32"}}
+    }));
+    let db = std::env::temp_dir().join(format!("observer-{}.db", uuid::Uuid::new_v4()));
+    let sql = crate::backends::sql::Sql::builder(events.to_str().unwrap())
+        .with_sqlite(db.to_str().unwrap())
+        .build()
+        .unwrap();
+    let ctx = crate::Context::new("checkout".to_string());
+    ctx.start_span("pay");
+    ctx.observe_span_field("amount", json!("seven"));
+    ctx.observe_span_result(json!(7));
+    ctx.end_span(false, None);
+    ctx.finalise();
+    let error = sql.context_ended(&ctx).unwrap_err().to_string();
+
+    let stored: (Option<i64>, Option<i64>) = rusqlite::Connection::open(&db)
+        .unwrap()
+        .query_row("SELECT result, field_amount FROM pay", [], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
+        .unwrap();
+    let _ = std::fs::remove_file(&events);
+    let _ = std::fs::remove_file(&db);
+    assert_eq!(stored, (Some(7), None));
+    assert_eq!(
+        error,
+        r#"stored context checkout without pay.amount: "seven" is not Integer"#
+    );
+}
+
+#[test]
+fn prometheus_renders_counters_and_histogram_buckets() {
+    use crate::Backend;
+    let prometheus = crate::backends::prometheus::Prometheus::builder()
+        .with_buckets(&[1.0, 5.0])
+        .with_label("plan", 1)
+        .build();
+    let metrics = prometheus.metrics();
+    for (plan, seconds, err) in [("gold", 2, None), ("silver", 0, Some("declined"))] {
+        let ctx = crate::Context::new("checkout".to_string());
+        ctx.start_span("pay");
+        ctx.observe_span_field("plan", json!(plan));
+        ctx.end_span(false, err.map(|e| e.to_string()));
+        ctx.finalise();
+        {
+            let mut stack = ctx.span_stack.borrow_mut();
+            let span = &mut stack[0].sub_frames[0];
+            span.end_time = Some(span.start_time + chrono::Duration::seconds(seconds));
+        }
+        prometheus.context_ended(&ctx).unwrap();
+    }
+
+    let rendered = metrics.render();
+    for line in [
+        r#"observer_span_duration_seconds_bucket{span="pay",plan="gold",le="1"} 0"#,
+        r#"observer_span_duration_seconds_bucket{span="pay",plan="gold",le="5"} 1"#,
+        r#"observer_span_duration_seconds_bucket{span="pay",plan="gold",le="+Inf"} 1"#,
+        r#"observer_span_duration_seconds_sum{span="pay",plan="gold"} 2"#,
+        r#"observer_span_duration_seconds_count{span="pay",plan="gold"} 1"#,
+        r#"observer_span_duration_seconds_bucket{span="pay",plan="__other__",le="1"} 1"#,
+        r#"observer_span_total{span="pay",plan="gold",outcome="success"} 1"#,
+        r#"observer_span_total{span="pay",plan="__other__",outcome="failure"} 1"#,
+    ] {
+        assert!(
+            rendered.lines().any(|l| l == line),
+            "{}\n{}",
+            line,
+            rendered
+        );
+    }
+}
+
+#[test]
+#[should_panic(expected = "is reserved")]
+fn prometheus_rejects_reserved_labels() {
+    crate::backends::prometheus::Prometheus::builder().with_label("le", 10);
+}
+
+#[test]
+#[should_panic(expected = "both map to label")]
+fn prometheus_rejects_colliding_labels() {
+    crate::backends::prometheus::Prometheus::builder()
+        .with_label("a-b", 10)
+        .with_label("a_b", 10);
+}
+
+#[cfg(feature = "sentry")]
+#[test]
+fn sentry_build_rejects_invalid_dsns() {
+    let error = crate::backends::sentry::Sentry::builder("https://o0.ingest.sentry.io")
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.to_string(),
+        r#"Invalid sentry DSN "https://o0.ingest.sentry.io""#
+    );
+}
+
+#[cfg(feature = "sentry")]
+#[test]
+fn sentry_envelopes_carry_the_error_event() {
+    let sentry = crate::backends::sentry::Sentry::builder("https://key@o0.ingest.sentry.io/42")
+        .with_environment("test")
+        .with_tag("team", "payments");
+    let ctx = crate::Context::new("checkout".to_string());
+    ctx.start_span("order");
+    ctx.observe_span_field("order_id", json!(7));
+    ctx.start_span("pay");
+    ctx.span_log("charging");
+    ctx.end_span(false, Some("declined\nbacktrace".to_string()));
+    ctx.end_span(false, None);
+    ctx.finalise();
+    let stack = ctx.span_stack.borrow();
+    let order = &stack[0].sub_frames[0];
+    let envelope = sentry.envelope(&ctx, &[order], &order.sub_frames[0]);
+
+    let lines: Vec<&str> = envelope.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(envelope.ends_with('\n'));
+    let header: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+    let item: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+    let event: serde_json::Value = serde_json::from_str(lines[2]).unwrap();
+    assert_eq!(header["event_id"], event["event_id"]);
+    assert_eq!(item, json!({"type": "event", "length": lines[2].len()}));
+    assert_eq!(event["level"], json!("error"));
+    assert_eq!(event["environment"], json!("test"));
+    assert_eq!(
+        event["exception"]["values"],
+        json!([{"type": "pay", "value": "declined"}])
+    );
+    assert_eq!(event["fingerprint"], json!(["pay", "declined"]));
+    assert_eq!(event["extra"]["error"], json!("declined\nbacktrace"));
+    assert_eq!(event["tags"]["team"], json!("payments"));
+    assert_eq!(event["tags"]["context_id"], json!("checkout"));
+    let breadcrumbs = &event["breadcrumbs"]["values"];
+    assert_eq!(breadcrumbs[0]["message"], json!("order"));
+    assert_eq!(breadcrumbs[0]["data"], json!({"order_id": 7}));
+    assert_eq!(breadcrumbs[1]["message"], json!("charging"));
+}
+
+/// A Sentry on a local port, sending the body of every request it answers to the receiver. It
+/// never answers when `answer` is false.
+#[cfg(feature = "sentry")]
+fn fake_sentry(answer: bool) -> (String, std::sync::mpsc::Receiver<String>) {
+    use std::io::{BufRead, Read, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let dsn = format!("http://key@{}/42", listener.local_addr().unwrap());
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = std::io::BufReader::new(stream.unwrap());
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                stream.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; length];
+            stream.read_exact(&mut body).unwrap();
+            if !answer {
+                std::thread::sleep(std::time::Duration::from_secs(5));
+                continue;
+            }
+            let _ = sender.send(String::from_utf8(body).unwrap());
+            let _ = stream
+                .get_mut()
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+        }
+    });
+    (dsn, receiver)
+}
+
+/// A context whose `pay` span failed.
+#[cfg(feature = "sentry")]
+fn declined() -> crate::Context {
+    let ctx = crate::Context::new("checkout".to_string());
+    ctx.start_span("pay");
+    ctx.end_span(false, Some("declined".to_string()));
+    ctx.finalise();
+    ctx
+}
+
+#[cfg(feature = "sentry")]
+#[test]
+fn sentry_app_ended_waits_for_queued_events() {
+    use crate::Backend;
+    let (dsn, received) = fake_sentry(true);
+    let sentry = crate::backends::sentry::Sentry::builder(&dsn)
+        .build()
+        .unwrap();
+    sentry.context_ended(&declined()).unwrap();
+    sentry.app_ended().unwrap();
+    let envelope = received.try_recv().unwrap();
+    assert!(envelope.contains(r#""value":"declined""#), "{}", envelope);
+    // nothing is left to wait for the second time
+    sentry.app_ended().unwrap();
+}
+
+#[cfg(feature = "sentry")]
+#[test]
+fn sentry_app_ended_gives_up_after_the_shutdown_timeout() {
+    use crate::Backend;
+    let (dsn, _received) = fake_sentry(false);
+    let sentry = crate::backends::sentry::Sentry::builder(&dsn)
+        .with_shutdown_timeout(std::time::Duration::from_millis(100))
+        .build()
+        .unwrap();
+    sentry.context_ended(&declined()).unwrap();
+    let started = std::time::Instant::now();
+    let error = sentry.app_ended().unwrap_err();
+    assert!(started.elapsed() < std::time::Duration::from_secs(2));
+    assert_eq!(
+        error.to_string(),
+        "sentry events still not sent after 100ms"
+    );
+}
+
+#[test]
+fn chrome_trace_has_complete_events_for_spans_and_instants_for_logs() {
+    use crate::Backend;
+    let ctx = crate::Context::new("checkout".to_string());
+    ctx.start_span("pay");
+    ctx.observe_span_field("amount", json!(7));
+    ctx.observe_span_result(json!(1));
+    ctx.span_log("charging");
+    ctx.end_span(false, Some("declined".to_string()));
+    ctx.finalise();
+    let (start, log_at) = {
+        let mut stack = ctx.span_stack.borrow_mut();
+        let pay = &mut stack[0].sub_frames[0];
+        pay.end_time = Some(pay.start_time + chrono::Duration::microseconds(1500));
+        (pay.start_time, pay.logs[0].0)
+    };
+    let micros = |at: chrono::DateTime<chrono::Utc>| at.timestamp_micros();
+
+    let events = crate::backends::chrome_trace::trace_events(&ctx);
+    assert_eq!(events.len(), 4);
+    assert_eq!(events[0]["ph"], json!("M"));
+    assert_eq!(events[1]["name"], json!("checkout"));
+    assert_eq!(events[1]["cat"], json!("context"));
+    assert_eq!(events[1]["ph"], json!("X"));
+    let pay = &events[2];
+    assert_eq!(pay["name"], json!("pay"));
+    assert_eq!(pay["ph"], json!("X"));
+    assert_eq!(pay["ts"], json!(micros(start)));
+    assert_eq!(pay["dur"], json!(1500));
+    assert_eq!(
+        pay["args"],
+        json!({"amount": 7, "@@success": false, "#result": 1, "#error": "declined"})
+    );
+    assert_eq!(events[3]["name"], json!("charging"));
+    assert_eq!(events[3]["ph"], json!("i"));
+    assert_eq!(events[3]["ts"], json!(micros(log_at)));
+    for event in events.iter() {
+        assert_eq!(event["tid"], events[0]["tid"]);
+    }
+
+    let directory = std::env::temp_dir().join(format!("observer-{}", uuid::Uuid::new_v4()));
+    let rolling = directory.join("trace.json");
+    crate::backends::chrome_trace::ChromeTrace::builder()
+        .with_directory(directory.to_str().unwrap())
+        .build()
+        .context_ended(&ctx)
+        .unwrap();
+    let written = directory.join(format!("checkout-{}.json", ctx.get_key()));
+    let trace: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(written).unwrap()).unwrap();
+    assert_eq!(trace["traceEvents"], json!(events));
+
+    let rolled = crate::backends::chrome_trace::ChromeTrace::builder()
+        .with_rolling_file(rolling.to_str().unwrap(), 1 << 20)
+        .build();
+    rolled.context_ended(&ctx).unwrap();
+    rolled.context_ended(&ctx).unwrap();
+    let text = std::fs::read_to_string(&rolling).unwrap();
+    let _ = std::fs::remove_dir_all(&directory);
+    assert!(text.starts_with("[\n"), "{}", text);
+    assert_eq!(text.lines().count(), 1 + 2 * events.len());
+    // the JSON array format allows the trailing comma and the missing `]`
+    let closed = format!("{}]", text.trim_end().trim_end_matches(','));
+    let appended: Vec<serde_json::Value> = serde_json::from_str(&closed).unwrap();
+    assert_eq!(appended.len(), 2 * events.len());
+}
+
+/// `checkout > pay > charge`, `pay` with a `password` and an `amount` field and a log.
+fn checkout_context() -> crate::Context {
+    let ctx = crate::Context::new("checkout".to_string());
+    ctx.start_span("pay");
+    ctx.observe_span_field("password", json!("hunter2"));
+    ctx.observe_span_field("amount", json!(7));
+    ctx.span_log("charging");
+    ctx.start_span("charge");
+    ctx.end_span(false, None);
+    ctx.end_span(true, None);
+    ctx.finalise();
+    ctx
+}
+
+/// What `logger` writes to its file for `ctx`.
+fn logged(logger: crate::backends::logger::Logger, ctx: &crate::Context) -> String {
+    use crate::Backend;
+    let path = std::env::temp_dir().join(format!("observer-{}.log", uuid::Uuid::new_v4()));
+    let logger = logger
+        .with_path(path.to_str().unwrap())
+        .with_pattern("{d} - {m}{n}")
+        .build()
+        .unwrap();
+    logger.context_ended(ctx).unwrap();
+    let written = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    written
+}
+
+#[test]
+fn logger_writes_one_logfmt_line_per_span_and_log() {
+    use crate::backends::logger::{Format, Logger};
+    let written = logged(
+        Logger::builder().with_format(Format::Logfmt),
+        &checkout_context(),
+    );
+    let lines: Vec<_> = written.lines().collect();
+    assert_eq!(lines.len(), 3, "{}", written);
+    for line in lines.iter() {
+        assert!(line.starts_with("ts="), "{}", written);
+    }
+    assert!(
+        lines[0].contains(" span=pay parent=main depth=1 "),
+        "{}",
+        written
+    );
+    assert!(
+        lines[0].contains(" field.amount=7 field.password=hunter2 "),
+        "{}",
+        written
+    );
+    assert!(lines[0].ends_with(" success=true"), "{}", written);
+    assert!(lines[1].ends_with(" span=pay log=charging"), "{}", written);
+    assert!(
+        lines[2].contains(" span=charge parent=pay depth=2 "),
+        "{}",
+        written
+    );
+}
+
+#[test]
+fn logger_writes_one_json_line_per_context() {
+    use crate::backends::logger::{Format, Logger};
+    let ctx = checkout_context();
+    let written = logged(Logger::builder().with_format(Format::Json), &ctx);
+    let lines: Vec<_> = written.lines().collect();
+    assert_eq!(lines.len(), 1, "{}", written);
+    let logged: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+    assert_eq!(logged["context"], json!("checkout"));
+    let pay = &logged["spans"][0];
+    assert_eq!(pay["id"], json!("pay"));
+    assert_eq!(
+        pay["breadcrumbs"],
+        json!({"password": "hunter2", "amount": 7})
+    );
+    assert_eq!(pay["logs"][0]["log"], json!("charging"));
+    assert_eq!(pay["spans"][0]["id"], json!("charge"));
+}
+
+#[test]
+fn logger_max_depth_and_hidden_fields_trim_every_format() {
+    use crate::backends::logger::{Format, Logger};
+    let ctx = checkout_context();
+    let trimmed = |format| {
+        logged(
+            Logger::builder()
+                .with_format(format)
+                .with_max_depth(1)
+                .hide_field("password"),
+            &ctx,
+        )
+    };
+
+    let tree = trimmed(Format::Tree);
+    assert!(tree.contains("pay: "), "{}", tree);
+    assert!(tree.contains("@amount: 7"), "{}", tree);
+    assert!(!tree.contains("charge: "), "{}", tree);
+    assert!(!tree.contains("hunter2"), "{}", tree);
+
+    let logfmt = trimmed(Format::Logfmt);
+    assert_eq!(logfmt.lines().count(), 2, "{}", logfmt);
+    assert!(logfmt.contains("field.amount=7"), "{}", logfmt);
+    assert!(!logfmt.contains("span=charge"), "{}", logfmt);
+    assert!(!logfmt.contains("password"), "{}", logfmt);
+
+    let json = trimmed(Format::Json);
+    let logged: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(logged["spans"][0]["breadcrumbs"], json!({"amount": 7}));
+    assert_eq!(logged["spans"][0]["spans"], json!([]));
+}
+
+#[test]
+fn logger_reports_a_log_file_it_can_not_open() {
+    let path = std::env::temp_dir().join(format!("observer-{}/missing.log", uuid::Uuid::new_v4()));
+    let error = crate::backends::logger::Logger::builder()
+        .with_path(path.to_str().unwrap())
+        .build()
+        .err()
+        .unwrap();
+    assert!(
+        error
+            .to_string()
+            .starts_with(&format!("Failed to open log file {}: ", path.display())),
+        "{}",
+        error
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn logger_reopens_the_file_on_sighup() {
+    use crate::Backend;
+    let path = std::env::temp_dir().join(format!("observer-{}.log", uuid::Uuid::new_v4()));
+    let rotated = path.with_extension("log.1");
+    let logger = crate::backends::logger::Logger::builder()
+        .with_path(path.to_str().unwrap())
+        .with_pattern("{m}{n}")
+        .with_reopen_on_sighup()
+        .build()
+        .unwrap();
+    logger
+        .context_ended(&crate::Context::new("before".to_string()))
+        .unwrap();
+    std::fs::rename(&path, &rotated).unwrap();
+    signal_hook::low_level::raise(signal_hook::consts::SIGHUP).unwrap();
+    logger
+        .context_ended(&crate::Context::new("after".to_string()))
+        .unwrap();
+
+    let before = std::fs::read_to_string(&rotated).unwrap();
+    let after = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&rotated);
+    let _ = std::fs::remove_file(&path);
+    assert!(before.starts_with("context: before "), "{}", before);
+    assert!(!before.contains("after"), "{}", before);
+    assert!(after.starts_with("context: after "), "{}", after);
+}
+
+#[test]
+fn logger_writes_to_stderr() {
+    use crate::backends::logger::{Format, Logger};
+    use crate::Backend;
+    // the child run of this test, whose stderr is checked below
+    if std::env::var_os("OBSERVER_LOGGER_STDERR").is_some() {
+        let logger = Logger::builder()
+            .with_stderr()
+            .with_format(Format::Logfmt)
+            .build()
+            .unwrap();
+        logger.context_ended(&checkout_context()).unwrap();
+        return;
+    }
+    let output = std::process::Command::new(std::env::current_exe().unwrap())
+        .args(["tests::logger_writes_to_stderr", "--exact", "--nocapture"])
+        .env("OBSERVER_LOGGER_STDERR", "1")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let logged: Vec<_> = stderr.lines().filter(|l| l.starts_with("ts=")).collect();
+    assert_eq!(logged.len(), 3, "{}", stderr);
+    assert!(logged[0].contains(" span=pay "), "{}", stderr);
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("span=pay"));
+}
+
+#[test]
+fn logger_rejects_invalid_date_formats() {
+    let error = crate::backends::logger::Logger::builder()
+        .with_pattern("{d(%Y-%Q)} {m}{n}")
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.to_string(),
+        r#"Invalid date format "%Y-%Q" in log pattern "{d(%Y-%Q)} {m}{n}""#
+    );
+}
+
+/// Records the size of every batch it is asked to send, failing the first `failures` sends.
+#[derive(Clone, Default)]
+struct Recorder {
+    sends: std::sync::Arc<std::sync::Mutex<Vec<usize>>>,
+    failures: usize,
+    retriable: bool,
+}
+
+impl crate::backends::batch::Exporter for Recorder {
+    type Item = String;
+
+    fn encode(&self, ctx: &crate::Context) -> Vec<String> {
+        vec![ctx.id()]
+    }
+
+    fn send(&self, batch: &[String]) -> crate::Result<()> {
+        let mut sends = self.sends.lock().unwrap();
+        sends.push(batch.len());
+        if sends.len() <= self.failures {
+            return Err(failure::format_err!("rejected"));
+        }
+        Ok(())
+    }
+
+    fn retriable(&self, _error: &failure::Error) -> bool {
+        self.retriable
+    }
+}
+
+/// `recorder` batched with an hour long interval, so only `flush` sends.
+fn batch(recorder: &Recorder) -> Box<crate::backends::batch::Batch<Recorder>> {
+    let hour = std::time::Duration::from_secs(3600);
+    let retry = std::time::Duration::from_millis(1);
+    crate::backends::batch::Batch::builder(recorder.clone())
+        .with_interval(hour)
+        .with_retries(3, retry, retry)
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn batch_retries_retriable_errors() {
+    use crate::Backend;
+    let recorder = Recorder {
+        failures: 2,
+        retriable: true,
+        ..Recorder::default()
+    };
+    let batch = batch(&recorder);
+    batch
+        .context_ended(&crate::Context::new("retried".to_string()))
+        .unwrap();
+    batch.flush();
+    assert_eq!(*recorder.sends.lock().unwrap(), vec![1, 1, 1]);
+}
+
+#[test]
+fn batch_drops_what_fails_with_other_errors() {
+    use crate::Backend;
+    let recorder = Recorder {
+        failures: 1,
+        retriable: false,
+        ..Recorder::default()
+    };
+    let batch = batch(&recorder);
+    batch
+        .context_ended(&crate::Context::new("rejected".to_string()))
+        .unwrap();
+    batch.flush();
+    assert_eq!(*recorder.sends.lock().unwrap(), vec![1]);
+
+    // the rejected batch is dropped, not sent again
+    batch
+        .context_ended(&crate::Context::new("next".to_string()))
+        .unwrap();
+    batch.flush();
+    assert_eq!(*recorder.sends.lock().unwrap(), vec![1, 1]);
+}
+
+#[test]
+fn flamegraph_writer_stops_at_app_ended() {
+    use crate::Backend;
+    let directory = std::env::temp_dir().join(format!("observer-{}", uuid::Uuid::new_v4()));
+    let files = || std::fs::read_dir(&directory).unwrap().count();
+    let ended = || {
+        let ctx = crate::Context::new("folded".to_string());
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        ctx.finalise();
+        ctx
+    };
+    let flamegraph = crate::backends::flamegraph::Flamegraph::builder(directory.to_str().unwrap())
+        .with_interval(std::time::Duration::from_millis(10))
+        .build();
+    flamegraph.app_started().unwrap();
+    // started again, still one writer
+    flamegraph.app_started().unwrap();
+    flamegraph.context_ended(&ended()).unwrap();
+    flamegraph.app_ended().unwrap();
+    let written = files();
+    assert!(written >= 1);
+
+    // nothing writes what comes after the end
+    flamegraph.context_ended(&ended()).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    let after = files();
+    let _ = std::fs::remove_dir_all(&directory);
+    assert_eq!(after, written);
+}
@@ -0,0 +1,274 @@
+//! Both ways between observer and `tracing`.
+//!
+//! ```ignore
+//! use tracing_subscriber::prelude::*;
+//!
+//! tracing_subscriber::registry()
+//!     .with(observer::tracing_bridge::ObserverLayer::new())
+//!     .init();
+//! observer::builder(observer::tracing_bridge::TracingBackend::builder().build()).init();
+//! ```
+use std::cell::RefCell;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+
+/// Target of everything `TracingBackend` emits, `ObserverLayer` skips it to avoid loops.
+static TARGET: &str = "observer";
+
+thread_local! {
+    /// `tracing` spans that started an observer span on this thread and are not closed yet,
+    /// innermost last.
+    static STARTED: RefCell<Vec<Id>> = const { RefCell::new(vec![]) };
+    /// Spans `TracingBackend` has open on this thread, innermost last.
+    static OPEN: RefCell<Vec<tracing::span::EnteredSpan>> = const { RefCell::new(vec![]) };
+}
+
+/// `tracing_subscriber::Layer` turning `tracing` spans into observer spans of the context
+/// active on the thread, and events into logs of the current span.
+///
+/// A span is started when the `tracing` span is first entered and ended when it is closed, so
+/// a future entered on every poll is a single span. It is named after the `tracing` span, with
+/// its fields as breadcrumbs. Closing a span also ends the spans started inside it that are
+/// still open. Outside a context nothing is recorded.
+pub struct ObserverLayer;
+
+impl ObserverLayer {
+    pub fn new() -> Self {
+        ObserverLayer
+    }
+}
+
+impl Default for ObserverLayer {
+    fn default() -> Self {
+        ObserverLayer::new()
+    }
+}
+
+/// Fields of a `tracing` span, kept in its extensions until it is entered.
+struct Fields(Vec<(String, serde_json::Value)>);
+
+/// Marks a `tracing` span that has started an observer span, kept in its extensions.
+struct Started;
+
+struct Visitor<'a>(&'a mut Vec<(String, serde_json::Value)>);
+
+impl Visit for Visitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.push((field.name().to_string(), json!(value)));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.push((field.name().to_string(), json!(value)));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.push((field.name().to_string(), json!(value)));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.push((field.name().to_string(), json!(value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push((field.name().to_string(), json!(value)));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .push((field.name().to_string(), json!(format!("{:?}", value))));
+    }
+}
+
+fn is_observer(metadata: &Metadata) -> bool {
+    metadata.target() == TARGET
+}
+
+fn observe_fields(fields: &[(String, serde_json::Value)]) {
+    for (key, value) in fields.iter() {
+        crate::observe_field(key, value.clone());
+    }
+}
+
+impl<S> tracing_subscriber::Layer<S> for ObserverLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if is_observer(attrs.metadata()) {
+            return;
+        }
+        let mut fields = vec![];
+        attrs.record(&mut Visitor(&mut fields));
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(Fields(fields));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let mut fields = vec![];
+        values.record(&mut Visitor(&mut fields));
+        let current = STARTED.with(|started| started.borrow().last() == Some(id));
+        if current && crate::context_available() {
+            observe_fields(&fields);
+        }
+        if let Some(span) = ctx.span(id) {
+            if let Some(known) = span.extensions_mut().get_mut::<Fields>() {
+                known.0.extend(fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if is_observer(event.metadata()) || !crate::context_available() {
+            return;
+        }
+        let mut fields = vec![];
+        event.record(&mut Visitor(&mut fields));
+        let mut line = format!(
+            "{} {}:",
+            event.metadata().level(),
+            event.metadata().target()
+        );
+        for (key, value) in fields.iter() {
+            let value = match value {
+                serde_json::Value::String(s) => s.to_string(),
+                v => v.to_string(),
+            };
+            if key == "message" {
+                line.push_str(&format!(" {}", value));
+            } else {
+                line.push_str(&format!(" {}={}", key, value));
+            }
+        }
+        crate::try_span_log(&line);
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        if is_observer(span.metadata())
+            || span.extensions().get::<Started>().is_some()
+            || !crate::context_available()
+        {
+            return;
+        }
+        crate::start_span(span.name());
+        if let Some(fields) = span.extensions().get::<Fields>() {
+            observe_fields(&fields.0);
+        }
+        span.extensions_mut().insert(Started);
+        STARTED.with(|started| started.borrow_mut().push(id.clone()));
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        match ctx.span(&id) {
+            Some(span) if span.extensions().get::<Started>().is_some() => {}
+            _ => return,
+        }
+        let ended = STARTED.with(|started| {
+            let mut started = started.borrow_mut();
+            match started.iter().position(|started| *started == id) {
+                Some(position) => started.drain(position..).count(),
+                None => 0,
+            }
+        });
+        if crate::context_available() {
+            for _ in 0..ended {
+                crate::end_span(false, None);
+            }
+        }
+    }
+}
+
+/// Re-emits contexts and observer spans as `tracing` spans with target `observer`, so
+/// subscribers see them nested with the application's own spans.
+///
+/// `tracing` span names are static, so contexts are `context` spans with a `context_id` field
+/// and observer spans are `span` spans with an `id` field. Success, error, result and
+/// breadcrumbs (as JSON) are recorded when the span ends, its logs are emitted as events.
+pub struct TracingBackend;
+
+impl TracingBackend {
+    pub fn builder() -> Self {
+        TracingBackend
+    }
+
+    pub fn build(self) -> Box<Self> {
+        Box::new(self)
+    }
+}
+
+impl crate::Backend for TracingBackend {
+    fn app_started(&self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn app_ended(&self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn context_created(&self, id: &str) -> crate::Result<()> {
+        let span = tracing::info_span!(target: "observer", "context", context_id = id);
+        OPEN.with(|open| open.borrow_mut().push(span.entered()));
+        Ok(())
+    }
+
+    fn context_ended(&self, _ctx: &crate::Context) -> crate::Result<()> {
+        OPEN.with(|open| {
+            let mut open = open.borrow_mut();
+            while let Some(span) = open.pop() {
+                drop(span);
+            }
+        });
+        Ok(())
+    }
+
+    fn span_created(&self, id: &str) -> crate::Result<()> {
+        let span = tracing::info_span!(
+            target: "observer",
+            "span",
+            id = id,
+            success = tracing::field::Empty,
+            error = tracing::field::Empty,
+            result = tracing::field::Empty,
+            breadcrumbs = tracing::field::Empty,
+        );
+        OPEN.with(|open| open.borrow_mut().push(span.entered()));
+        Ok(())
+    }
+
+    fn span_data(&self, _key: &str, _value: &str) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn span_ended(&self, parent: Option<&crate::span::Span>) -> crate::Result<()> {
+        let span = match OPEN.with(|open| open.borrow_mut().pop()) {
+            Some(span) => span,
+            None => return Ok(()),
+        };
+        // the ended span has just been added to its parent
+        if let Some(ended) = parent.and_then(|parent| parent.sub_frames.last()) {
+            if let Some(success) = ended.success {
+                span.record("success", success);
+            }
+            if let Some(err) = &ended.err {
+                span.record("error", err.as_str());
+            }
+            if let Some(result) = &ended.result {
+                span.record("result", result.to_string().as_str());
+            }
+            if !ended.breadcrumbs.is_empty() {
+                span.record("breadcrumbs", json!(ended.breadcrumbs).to_string().as_str());
+            }
+            for (at, log) in ended.logs.iter() {
+                tracing::info!(target: "observer", at = %at, "{}", log);
+            }
+        }
+        Ok(())
+    }
+}
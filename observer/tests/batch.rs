@@ -0,0 +1,44 @@
+//! `observer::end_app` sends what `Batch` still buffers, however long its interval.
+use observer::backends::batch::{Batch, Exporter};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Records the size of every batch it is asked to send.
+#[derive(Clone, Default)]
+struct Recorder {
+    sends: Arc<Mutex<Vec<usize>>>,
+}
+
+impl Exporter for Recorder {
+    type Item = String;
+
+    fn encode(&self, ctx: &observer::Context) -> Vec<String> {
+        vec![ctx.id().to_string()]
+    }
+
+    fn send(&self, batch: &[String]) -> observer::Result<()> {
+        self.sends.lock().unwrap().push(batch.len());
+        Ok(())
+    }
+}
+
+#[test]
+fn end_app_sends_the_final_batch() {
+    let recorder = Recorder::default();
+    observer::builder(
+        Batch::builder(recorder.clone())
+            .with_interval(Duration::from_secs(3600))
+            .build()
+            .unwrap(),
+    )
+    .init();
+
+    observer::create_context("first");
+    observer::end_context();
+    observer::create_context("second");
+    observer::end_context();
+    assert!(recorder.sends.lock().unwrap().is_empty());
+
+    observer::end_app();
+    assert_eq!(*recorder.sends.lock().unwrap(), vec![2]);
+}
@@ -0,0 +1,6 @@
+//! Functions `#[observed]` and `#[balanced_if]` reject, with the errors they give, in
+//! `tests/compile_fail`. `TRYBUILD=overwrite` updates the expected errors.
+#[test]
+fn compile_fail() {
+    trybuild::TestCases::new().compile_fail("tests/compile_fail/*.rs");
+}
@@ -0,0 +1,11 @@
+use observer_attribute::observed;
+
+#[observed(args = all, fields(amount = "i32"), result_type = "i32")]
+fn pay(amount: i32, token: &str) -> Result<i32, String> {
+    let _ = token;
+    Ok(amount)
+}
+
+fn main() {
+    let _ = pay(7, "t");
+}
@@ -0,0 +1,22 @@
+use observer::prelude::*;
+use observer_attribute::balanced_if;
+
+#[balanced_if]
+fn route(kind: &str, id: i32) {
+    if id > 0 {
+        observe_field("id", id);
+    } else {
+        observe_field("kind", kind);
+    }
+    match kind {
+        "card" => observe_field("id", id),
+        // diverging branches are not compared
+        "cash" => return,
+        "cheque" => panic!("not accepted"),
+        _ => {}
+    }
+}
+
+fn main() {
+    route("card", 1);
+}
@@ -0,0 +1,22 @@
+use observer::prelude::*;
+use observer_attribute::observed;
+
+#[observed(balanced, lenient)]
+fn refund(id: i32) -> Result<(), String> {
+    if id > 0 {
+        observe_field("id", id);
+    }
+    if id > 10 {
+        return Err("too late".to_string());
+    }
+    if id > 5 {
+        observe_field("late", true);
+    } else {
+        observe_field("late", false);
+    }
+    Ok(())
+}
+
+fn main() {
+    let _ = refund(1);
+}
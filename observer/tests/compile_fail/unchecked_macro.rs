@@ -0,0 +1,20 @@
+use observer::prelude::*;
+use observer_attribute::observed;
+
+macro_rules! twice {
+    ($e:expr) => {{
+        $e;
+        $e
+    }};
+}
+
+#[observed(fields(amount = "i32"), result_type = "i32")]
+fn pay(amount: i32) -> Result<i32, String> {
+    twice!(observe_field("amount", amount));
+    println!("{:?}", observe_field("payee", "acme"));
+    Ok(amount)
+}
+
+fn main() {
+    let _ = pay(7);
+}
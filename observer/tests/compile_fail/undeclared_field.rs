@@ -0,0 +1,13 @@
+use observer::prelude::*;
+use observer_attribute::observed;
+
+#[observed(fields(amount = "i32"), result_type = "i32")]
+fn pay(amount: i32) -> Result<i32, String> {
+    observe_field("amount", amount);
+    observe_field("payee", "acme");
+    Ok(amount)
+}
+
+fn main() {
+    let _ = pay(7);
+}
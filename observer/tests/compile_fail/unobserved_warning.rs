@@ -0,0 +1,13 @@
+#![deny(deprecated)]
+use observer::prelude::*;
+use observer_attribute::observed;
+
+#[observed(fields(amount = "i32", payee = "string"), result_type = "i32")]
+fn pay(amount: i32) -> Result<i32, String> {
+    observe_field("amount", amount);
+    Ok(amount)
+}
+
+fn main() {
+    let _ = pay(7);
+}
@@ -0,0 +1,67 @@
+//! `observer::end_app` ends every backend once and drops the global observer.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+struct Ending {
+    ended: Arc<AtomicUsize>,
+    panics: bool,
+}
+
+impl observer::Backend for Ending {
+    fn app_started(&self) -> observer::Result<()> {
+        Ok(())
+    }
+
+    fn app_ended(&self) -> observer::Result<()> {
+        self.ended.fetch_add(1, Ordering::SeqCst);
+        if self.panics {
+            panic!("failed to flush");
+        }
+        Ok(())
+    }
+
+    fn context_created(&self, _id: &str) -> observer::Result<()> {
+        Ok(())
+    }
+
+    fn context_ended(&self, _ctx: &observer::Context) -> observer::Result<()> {
+        Ok(())
+    }
+
+    fn span_created(&self, _id: &str) -> observer::Result<()> {
+        Ok(())
+    }
+
+    fn span_data(&self, _key: &str, _value: &str) -> observer::Result<()> {
+        Ok(())
+    }
+
+    fn span_ended(&self, _span: Option<&observer::span::Span>) -> observer::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn end_app_ends_every_backend_once() {
+    let ended = Arc::new(AtomicUsize::new(0));
+    let (errors, received) = std::sync::mpsc::sync_channel(10);
+    observer::builder(Box::new(Ending {
+        ended: ended.clone(),
+        panics: true,
+    }))
+    .add_backend(Box::new(Ending {
+        ended: ended.clone(),
+        panics: false,
+    }))
+    .with_error_channel(errors)
+    .init();
+
+    observer::end_app();
+    assert_eq!(ended.load(Ordering::SeqCst), 2);
+    let error = received.try_recv().expect("the panic was not reported");
+    assert_eq!((error.backend, error.method), (0, "app_ended"));
+
+    // the observer is gone, ending again does nothing
+    observer::end_app();
+    assert_eq!(ended.load(Ordering::SeqCst), 2);
+}
@@ -0,0 +1,28 @@
+//! `observer::end_app` writes the stacks `Flamegraph` folded since its last interval.
+use observer::backends::flamegraph::Flamegraph;
+
+#[test]
+fn end_app_writes_what_is_aggregated() {
+    let directory = std::env::temp_dir().join(format!("observer-{}", uuid::Uuid::new_v4()));
+    observer::builder(
+        Flamegraph::builder(directory.to_str().unwrap())
+            .with_interval(std::time::Duration::from_secs(3600))
+            .build(),
+    )
+    .init();
+
+    observer::create_context("folded");
+    std::thread::sleep(std::time::Duration::from_millis(2));
+    observer::end_context();
+    assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 0);
+
+    observer::end_app();
+    let files: Vec<_> = std::fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(files.len(), 1);
+    let folded = std::fs::read_to_string(&files[0]).unwrap();
+    let _ = std::fs::remove_dir_all(&directory);
+    assert!(folded.starts_with("main "), "{}", folded);
+}
@@ -0,0 +1,91 @@
+//! What `LogBridge`, the process wide `log` logger here, keeps in span logs and forwards.
+use observer::prelude::*;
+use observer::testing;
+use observer_attribute::observed;
+use std::sync::{Mutex, Once};
+
+/// Every record the forward logger got, as `LEVEL message`.
+static FORWARDED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct Forward;
+
+impl log::Log for Forward {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        FORWARDED
+            .lock()
+            .unwrap()
+            .push(format!("{} {}", record.level(), record.args()));
+    }
+
+    fn flush(&self) {}
+}
+
+fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        observer::log_bridge::LogBridge::builder()
+            .with_level(log::LevelFilter::Info)
+            .with_forward(Box::new(Forward))
+            .init()
+            .unwrap();
+    });
+}
+
+fn forwarded(message: &str) -> bool {
+    FORWARDED
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|forwarded| forwarded == message)
+}
+
+#[observed(lenient)]
+fn charge(run: &dyn Fn()) -> Result<(), String> {
+    run();
+    Ok(())
+}
+
+/// The logs of the span `run` runs in.
+fn logs(run: impl Fn()) -> Vec<String> {
+    init();
+    let (_, ctx) = testing::observe("bridged", || charge(&run));
+    testing::find_spans(&ctx, "charge")[0]
+        .logs
+        .iter()
+        .map(|(_, log)| log.clone())
+        .collect()
+}
+
+#[test]
+fn records_in_a_context_are_span_logs() {
+    let logs = logs(|| {
+        log::warn!(target: "payments", "charged {}", 7);
+        log::info!("charging");
+    });
+    assert_eq!(
+        logs,
+        [
+            "WARN payments (log_bridge): charged 7",
+            "INFO log_bridge: charging"
+        ]
+    );
+    assert!(forwarded("WARN charged 7"));
+}
+
+#[test]
+fn records_above_the_level_are_only_forwarded() {
+    let logs = logs(|| log::debug!("retrying the charge"));
+    assert!(logs.is_empty(), "{:?}", logs);
+    assert!(forwarded("DEBUG retrying the charge"));
+}
+
+#[test]
+fn records_without_a_context_are_forwarded() {
+    init();
+    log::error!("no context here");
+    assert!(forwarded("ERROR no context here"));
+}
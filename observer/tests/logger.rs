@@ -0,0 +1,26 @@
+//! `observer::end_app` flushes what `Logger` buffered for its file.
+use observer::backends::logger::Logger;
+
+#[test]
+fn end_app_flushes_the_buffered_file() {
+    let path = std::env::temp_dir().join(format!("observer-{}.log", uuid::Uuid::new_v4()));
+    observer::builder(
+        Logger::builder()
+            .with_path(path.to_str().unwrap())
+            .with_pattern("{d(%Y-%m-%d)} {m}{n}")
+            .with_buffer_size(64 * 1024)
+            .build()
+            .unwrap(),
+    )
+    .init();
+
+    observer::create_context("buffered");
+    observer::end_context();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+
+    observer::end_app();
+    let written = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert!(written.contains("logger_initialized"), "{}", written);
+    assert!(written.contains("context: buffered"), "{}", written);
+}
@@ -0,0 +1,124 @@
+//! What `#[observed]` records, for the parts that are checked at compile time.
+use observer::prelude::*;
+use observer::testing;
+use observer_attribute::observed;
+
+#[observed(fields(amount = "i32", payee = "string"), result_type = "i32")]
+fn pay(amount: i32, payee: &str) -> Result<i32, String> {
+    println!("paying {}", {
+        observe_field("amount", amount);
+        amount
+    });
+    let observed = format!("{:?}", observe_field("payee", payee));
+    assert_eq!(observed, "()");
+    observe_result(amount);
+    Ok(amount)
+}
+
+#[test]
+fn observe_calls_in_macros_are_recorded() {
+    let (result, ctx) = testing::observe("macros", || pay(7, "acme"));
+    assert_eq!(result, Ok(7));
+    testing::assert_field(&ctx, "pay", "amount", 7);
+    testing::assert_field(&ctx, "pay", "payee", "acme");
+}
+
+pub struct User {
+    name: String,
+}
+
+#[observed(
+    fields(id = "i32", owner = "string"),
+    fields(id, owner = user.name),
+    skip(user, token),
+    args = all,
+    result_type = "i32"
+)]
+fn share(id: i32, user: &User, token: &str) -> Result<i32, String> {
+    assert!(!user.name.is_empty());
+    Ok(id + token.len() as i32)
+}
+
+#[test]
+fn fields_name_arguments_and_expressions() {
+    let user = User {
+        name: "asha".to_string(),
+    };
+    let (result, ctx) = testing::observe("fields", || share(7, &user, "t"));
+    assert_eq!(result, Ok(8));
+    testing::assert_field(&ctx, "share", "id", 7);
+    testing::assert_field(&ctx, "share", "owner", "asha");
+    let breadcrumbs = &testing::find_spans(&ctx, "share")[0].breadcrumbs;
+    let mut recorded: Vec<_> = breadcrumbs.keys().collect();
+    recorded.sort();
+    assert_eq!(recorded, ["id", "owner"]);
+}
+
+#[derive(serde_derive::Serialize)]
+pub struct Serialized {
+    amount: i32,
+}
+
+#[derive(Debug)]
+pub struct Debugged {
+    #[allow(dead_code)]
+    amount: i32,
+}
+
+#[observed(lenient, args = all, skip(password))]
+fn log_in(serialized: Serialized, debugged: &Debugged, password: &str) -> Result<(), String> {
+    assert!(!password.is_empty());
+    let _ = (serialized.amount, debugged);
+    Ok(())
+}
+
+#[test]
+fn lenient_arguments_are_serialized_or_debug_formatted() {
+    let (_, ctx) = testing::observe("fallback", || {
+        log_in(Serialized { amount: 1 }, &Debugged { amount: 2 }, "hunter2")
+    });
+    testing::assert_field(
+        &ctx,
+        "log_in",
+        "serialized",
+        serde_json::json!({"amount": 1}),
+    );
+    testing::assert_field(&ctx, "log_in", "debugged", "Debugged { amount: 2 }");
+    assert!(testing::field(&ctx, "log_in", "password").is_none());
+}
+
+#[observed(fields(total = "i32"), result_type = "i32")]
+fn tally(observe_count: i32) -> Result<i32, String> {
+    let observe_total = observe_count * 2;
+    observe_field("total", observe_total);
+    let summary =
+        serde_json::json!({"n": observe_count, "log": observer::observe_span_log("tallied")});
+    assert_eq!(summary["n"], 3);
+    assert_eq!(format!("{}", observe_total), "6");
+    Ok(observe_total)
+}
+
+#[test]
+fn other_observe_identifiers_in_macros_compile() {
+    let (result, ctx) = testing::observe("tally", || tally(3));
+    assert_eq!(result, Ok(6));
+    testing::assert_field(&ctx, "tally", "total", 6);
+}
+
+#[observed(
+    namespace = "fn_test",
+    fields(name = "string", age = "i32"),
+    result_type = "i32"
+)]
+fn a(name: &str, age: i32) -> Result<i32, String> {
+    observe_field("name", name);
+    observe_field("age", age);
+    Ok(age)
+}
+
+#[test]
+fn critical_of_the_events_file_is_kept_when_not_given_inline() {
+    let (result, ctx) = testing::observe("critical", || a("asha", 7));
+    assert_eq!(result, Ok(7));
+    assert!(testing::find_spans(&ctx, "fn_test__a")[0].critical);
+}
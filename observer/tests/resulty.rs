@@ -0,0 +1,139 @@
+//! `#[derive(Resulty)]` and the summaries `#[observed(with_result)]` records.
+use observer::prelude::*;
+use observer::testing;
+use observer_attribute::{observed, Resulty};
+
+#[derive(Resulty)]
+struct Policy {
+    id: String,
+    owner_id: i64,
+    #[allow(dead_code)]
+    body: String,
+}
+
+#[derive(Resulty)]
+struct Marked {
+    #[resulty]
+    name: String,
+    #[allow(dead_code)]
+    id: i32,
+}
+
+#[derive(Resulty)]
+struct Plain {
+    a: i32,
+    b: Option<bool>,
+}
+
+#[derive(Resulty)]
+struct Pair(i32, String);
+
+#[derive(Resulty)]
+struct Wrapper<T>(T);
+
+#[derive(Resulty)]
+#[allow(dead_code)]
+enum Status {
+    Active,
+    Closed { at: i32 },
+    Other(i32),
+}
+
+struct Opaque;
+
+fn policy() -> Policy {
+    Policy {
+        id: "p1".to_string(),
+        owner_id: 7,
+        body: "long".to_string(),
+    }
+}
+
+#[test]
+fn marked_fields_are_the_summary() {
+    let marked = Marked {
+        name: "n".to_string(),
+        id: 1,
+    };
+    assert_eq!(marked.summary(), serde_json::json!({"name": "n"}));
+}
+
+#[test]
+fn id_fields_are_the_summary() {
+    assert_eq!(
+        policy().summary(),
+        serde_json::json!({"id": "p1", "owner_id": 7})
+    );
+}
+
+#[test]
+fn all_fields_without_ids() {
+    let plain = Plain { a: 1, b: None };
+    assert_eq!(plain.summary(), serde_json::json!({"a": 1, "b": null}));
+    assert_eq!(
+        Pair(1, "x".to_string()).summary(),
+        serde_json::json!([1, "x"])
+    );
+}
+
+#[test]
+fn enums_are_summarized_by_variant() {
+    assert_eq!(Status::Active.summary(), serde_json::json!("Active"));
+    assert_eq!(
+        Status::Closed { at: 3 }.summary(),
+        serde_json::json!("Closed")
+    );
+    assert_eq!(Status::Other(1).summary(), serde_json::json!("Other"));
+}
+
+#[test]
+fn generics_are_summarized_by_their_parameters() {
+    assert_eq!(Wrapper(policy()).summary(), policy().summary());
+    assert_eq!(
+        Wrapper(vec![Status::Active]).summary(),
+        serde_json::json!(["Active"])
+    );
+}
+
+#[observed(lenient, with_result)]
+fn load_policy() -> Result<Policy, String> {
+    Ok(policy())
+}
+
+#[observed(lenient, with_result)]
+fn load_opaque() -> Result<Opaque, String> {
+    Ok(Opaque)
+}
+
+fn record_count() {
+    observe_result_i32(3);
+}
+
+#[observed(lenient, with_result)]
+fn load_counted() -> Result<Policy, String> {
+    record_count();
+    Ok(policy())
+}
+
+#[test]
+fn with_result_records_the_summary() {
+    let (_, ctx) = testing::observe("summary", load_policy);
+    let spans = testing::find_spans(&ctx, "load_policy");
+    assert_eq!(spans[0].result, Some(policy().summary()));
+}
+
+#[test]
+fn with_result_skips_types_without_summary() {
+    let (result, ctx) = testing::observe("opaque", load_opaque);
+    assert!(result.is_ok());
+    let spans = testing::find_spans(&ctx, "load_opaque");
+    assert_eq!(spans[0].result, None);
+    assert_eq!(spans[0].success, Some(true));
+}
+
+#[test]
+fn with_result_keeps_a_recorded_result() {
+    let (_, ctx) = testing::observe("counted", load_counted);
+    let spans = testing::find_spans(&ctx, "load_counted");
+    assert_eq!(spans[0].result, Some(serde_json::json!(3)));
+}
@@ -0,0 +1,81 @@
+//! `ObserverLayer` records into the global observer's context, so it gets a process of its own.
+#![cfg(feature = "tracing")]
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::prelude::*;
+
+/// Keeps the span tree of every context that ended.
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<observer::span::Span>>>);
+
+impl observer::Backend for Recorder {
+    fn app_started(&self) -> observer::Result<()> {
+        Ok(())
+    }
+
+    fn app_ended(&self) -> observer::Result<()> {
+        Ok(())
+    }
+
+    fn context_created(&self, _id: &str) -> observer::Result<()> {
+        Ok(())
+    }
+
+    fn context_ended(&self, ctx: &observer::Context) -> observer::Result<()> {
+        let spans = ctx.span_stack.borrow();
+        // `Span::clone` drops everything observed, a round trip keeps it
+        let main = serde_json::from_value(serde_json::to_value(&spans[0]).unwrap()).unwrap();
+        self.0.lock().unwrap().push(main);
+        Ok(())
+    }
+
+    fn span_created(&self, _id: &str) -> observer::Result<()> {
+        Ok(())
+    }
+
+    fn span_data(&self, _key: &str, _value: &str) -> observer::Result<()> {
+        Ok(())
+    }
+
+    fn span_ended(&self, _span: Option<&observer::span::Span>) -> observer::Result<()> {
+        Ok(())
+    }
+}
+
+fn ids(span: &observer::span::Span) -> Vec<String> {
+    span.sub_frames.iter().map(|span| span.id.clone()).collect()
+}
+
+#[test]
+fn spans_last_from_first_enter_to_close() {
+    let recorder = Recorder::default();
+    observer::builder(Box::new(recorder.clone())).init();
+    let _subscriber = tracing::subscriber::set_default(
+        tracing_subscriber::registry().with(observer::tracing_bridge::ObserverLayer::new()),
+    );
+
+    observer::create_context("polled");
+    // a future is entered on every poll
+    let poll = tracing::info_span!("poll", attempt = 1);
+    for _ in 0..3 {
+        let _entered = poll.enter();
+    }
+    drop(poll);
+    let outer = tracing::info_span!("outer");
+    // started inside `outer` but closed after it, so ended with it
+    let inner = tracing::info_span!(parent: None, "inner");
+    outer.in_scope(|| inner.in_scope(|| {}));
+    drop(outer);
+    drop(inner);
+    observer::end_context();
+
+    let contexts = recorder.0.lock().unwrap();
+    assert_eq!(contexts.len(), 1);
+    let main = &contexts[0];
+    assert_eq!(ids(main), ["poll", "outer"]);
+    assert_eq!(
+        main.sub_frames[0].breadcrumbs["attempt"],
+        serde_json::json!(1)
+    );
+    assert!(main.sub_frames[0].end_time.is_some());
+    assert_eq!(ids(&main.sub_frames[1]), ["inner"]);
+}
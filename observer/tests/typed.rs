@@ -0,0 +1,96 @@
+//! What the `typed_*` functions `#[observed]` uses for the richer types of the events file
+//! record, and log when a value does not fit.
+use observer::prelude::*;
+use observer::testing;
+use observer_attribute::observed;
+
+#[observed(
+    fields(
+        when = "option<timestamp>",
+        took = "duration",
+        ids = "list<uuid>",
+        note = "option<string>"
+    ),
+    result_type = "i32"
+)]
+fn settle(
+    when: Option<chrono::DateTime<chrono::Utc>>,
+    took: std::time::Duration,
+    ids: Vec<uuid::Uuid>,
+    note: Option<String>,
+) -> Result<i32, String> {
+    observe_field("when", &when);
+    observe_field("took", &took);
+    observe_field("ids", &ids);
+    observe_field("note", &note);
+    Ok(1)
+}
+
+#[test]
+fn option_list_timestamp_duration_and_uuid_are_encoded() {
+    let when = chrono::DateTime::parse_from_rfc3339("2024-05-01T10:00:00Z")
+        .unwrap()
+        .with_timezone(&chrono::Utc);
+    let id = uuid::Uuid::nil();
+    let (_, ctx) = testing::observe("settle", || {
+        settle(
+            Some(when),
+            std::time::Duration::from_micros(1500),
+            vec![id],
+            None,
+        )
+    });
+    testing::assert_field(&ctx, "settle", "when", "2024-05-01T10:00:00+00:00");
+    testing::assert_field(&ctx, "settle", "took", 1.5);
+    testing::assert_field(
+        &ctx,
+        "settle",
+        "ids",
+        serde_json::json!(["00000000-0000-0000-0000-000000000000"]),
+    );
+    testing::assert_field(&ctx, "settle", "note", serde_json::Value::Null);
+}
+
+#[observed(lenient)]
+fn check(run: &dyn Fn()) -> Result<(), String> {
+    run();
+    Ok(())
+}
+
+/// The logs of the span `run` runs in.
+fn logs(run: impl Fn()) -> Vec<String> {
+    let (_, ctx) = testing::observe("typed", || check(&run));
+    testing::find_spans(&ctx, "check")[0]
+        .logs
+        .iter()
+        .map(|(_, log)| log.clone())
+        .collect()
+}
+
+#[test]
+fn enums_log_values_not_allowed() {
+    let logs = logs(|| {
+        assert_eq!(
+            typed_enum("plan", "gold", &["gold", "silver"]),
+            serde_json::json!("gold")
+        );
+        typed_enum("plan", "bronze", &["gold", "silver"]);
+    });
+    assert_eq!(
+        logs,
+        [r#"field plan: expected one of ["gold", "silver"], got "bronze""#]
+    );
+}
+
+#[test]
+fn objects_log_values_not_fitting_their_schema() {
+    static SCHEMA: ObjectSchema =
+        ObjectSchema::new(r#"{"type": "object", "fields": {"city": "string"}}"#);
+    let logs = logs(|| {
+        let valid = serde_json::json!({"city": "Pune"});
+        assert_eq!(typed_object("address", &valid, &SCHEMA), valid);
+        typed_object("address", &serde_json::json!({"city": 7}), &SCHEMA);
+    });
+    assert_eq!(logs.len(), 1, "{:?}", logs);
+    assert!(logs[0].starts_with("field address: "), "{:?}", logs);
+}
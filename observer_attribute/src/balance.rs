@@ -0,0 +1,166 @@
+//! Every branch of an `if` or `match` has to observe the same fields, or none of them, so a
+//! field missing from a span says something about the data and not about the path taken.
+//!
+//! Branches that never finish (`return`, `break`, `continue`, `panic!` and the like) are not
+//! compared, they do not produce a span with fields. An `if` without `else` has an empty else
+//! branch.
+use std::collections::BTreeSet;
+use syn::visit::Visit;
+
+const DIVERGING_MACROS: &[&str] = &["panic", "unreachable", "todo", "unimplemented"];
+
+/// Errors for every unbalanced `if` and `match` in `item`, nested ones included.
+pub(crate) fn check_item(item: &syn::Item) -> Option<syn::Error> {
+    let mut checker = Checker { errors: None };
+    checker.visit_item(item);
+    checker.errors
+}
+
+pub(crate) fn check_block(block: &syn::Block) -> Option<syn::Error> {
+    let mut checker = Checker { errors: None };
+    checker.visit_block(block);
+    checker.errors
+}
+
+struct Branch {
+    fields: BTreeSet<String>,
+    span: proc_macro2::Span,
+}
+
+struct Checker {
+    errors: Option<syn::Error>,
+}
+
+impl Checker {
+    fn compare(&mut self, branches: Vec<Branch>) {
+        let mut branches = branches.into_iter();
+        let first = match branches.next() {
+            Some(first) => first,
+            None => return,
+        };
+        for branch in branches.filter(|b| b.fields != first.fields) {
+            let error = syn::Error::new(
+                branch.span,
+                format!(
+                    "this branch observes {} but the first branch observes {}, every branch must \
+                     observe the same fields or none of them",
+                    names(&branch.fields),
+                    names(&first.fields)
+                ),
+            );
+            match self.errors.as_mut() {
+                Some(errors) => errors.combine(error),
+                None => self.errors = Some(error),
+            }
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for Checker {
+    fn visit_expr_if(&mut self, expr: &'ast syn::ExprIf) {
+        syn::visit::visit_expr_if(self, expr);
+        let mut branches = vec![];
+        if !block_diverges(&expr.then_branch) {
+            branches.push(Branch {
+                fields: fields(|c| c.visit_block(&expr.then_branch)),
+                span: expr.then_branch.brace_token.span,
+            });
+        }
+        match &expr.else_branch {
+            Some((_, else_branch)) if !diverges(else_branch) => branches.push(Branch {
+                fields: fields(|c| c.visit_expr(else_branch)),
+                span: syn::spanned::Spanned::span(else_branch.as_ref()),
+            }),
+            Some(_) => {}
+            None => branches.push(Branch {
+                fields: BTreeSet::new(),
+                span: expr.if_token.span,
+            }),
+        }
+        self.compare(branches);
+    }
+
+    fn visit_expr_match(&mut self, expr: &'ast syn::ExprMatch) {
+        syn::visit::visit_expr_match(self, expr);
+        let branches = expr
+            .arms
+            .iter()
+            .filter(|arm| !diverges(&arm.body))
+            .map(|arm| Branch {
+                fields: fields(|c| c.visit_expr(&arm.body)),
+                span: syn::spanned::Spanned::span(&arm.pat),
+            })
+            .collect();
+        self.compare(branches);
+    }
+}
+
+/// Keys of the `observe_field` calls in what `visit` visits, nested items left out.
+fn fields(visit: impl FnOnce(&mut Fields)) -> BTreeSet<String> {
+    let mut collector = Fields(BTreeSet::new());
+    visit(&mut collector);
+    collector.0
+}
+
+struct Fields(BTreeSet<String>);
+
+impl<'ast> Visit<'ast> for Fields {
+    fn visit_expr_call(&mut self, call: &'ast syn::ExprCall) {
+        syn::visit::visit_expr_call(self, call);
+        if let syn::Expr::Path(p) = call.func.as_ref() {
+            if p.path.is_ident("observe_field") {
+                if let Some(syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Str(s),
+                    ..
+                })) = call.args.first()
+                {
+                    self.0.insert(s.value());
+                }
+            }
+        }
+    }
+
+    fn visit_macro(&mut self, mac: &'ast syn::Macro) {
+        if let Some(args) = crate::macros::args(mac) {
+            args.iter().for_each(|arg| self.visit_expr(arg));
+        }
+    }
+
+    fn visit_item(&mut self, item: &'ast syn::Item) {
+        if let syn::Item::Macro(item) = item {
+            if item.ident.is_none() {
+                self.visit_macro(&item.mac);
+            }
+        }
+    }
+}
+
+fn diverges(expr: &syn::Expr) -> bool {
+    match expr {
+        syn::Expr::Return(_) | syn::Expr::Break(_) | syn::Expr::Continue(_) => true,
+        syn::Expr::Macro(m) => DIVERGING_MACROS.iter().any(|d| m.mac.path.is_ident(d)),
+        syn::Expr::Block(b) => block_diverges(&b.block),
+        _ => false,
+    }
+}
+
+fn block_diverges(block: &syn::Block) -> bool {
+    match block.stmts.last() {
+        Some(syn::Stmt::Expr(e)) | Some(syn::Stmt::Semi(e, _)) => diverges(e),
+        Some(syn::Stmt::Item(syn::Item::Macro(m))) => {
+            DIVERGING_MACROS.iter().any(|d| m.mac.path.is_ident(d))
+        }
+        _ => false,
+    }
+}
+
+fn names(fields: &BTreeSet<String>) -> String {
+    if fields.is_empty() {
+        return "no fields".to_string();
+    }
+    fields
+        .iter()
+        .map(|f| format!("\"{}\"", f))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
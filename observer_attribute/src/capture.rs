@@ -0,0 +1,201 @@
+//! Arguments `#[observed]` records without `observe_field` calls.
+//!
+//! `fields(user_id, amount)` records the named arguments and `fields(user_id = user.id)` an
+//! expression under the given field. `args = all` records every argument, but those in
+//! `skip(password)`; for typed events the arguments that are not fields of the event are errors,
+//! so they have to be skipped.
+//!
+//! `fields(id = "string")` stays the inline definition of a field. Darling only takes literals,
+//! so the capture parts are taken out of the attribute here and the rest is left to it.
+use proc_macro2::{Delimiter, TokenStream, TokenTree};
+use syn::parse::{Parse, ParseStream, Parser};
+use syn::punctuated::Punctuated;
+
+#[derive(Default)]
+pub(crate) struct Capture {
+    /// The expression is `None` for the argument of that name.
+    fields: Vec<(syn::Ident, Option<syn::Expr>)>,
+    skip: Vec<syn::Ident>,
+    all: bool,
+}
+
+enum FieldArg {
+    Name(syn::Ident),
+    Inline(syn::Ident, syn::LitStr),
+    Expr(syn::Ident, Box<syn::Expr>),
+}
+
+impl Parse for FieldArg {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name: syn::Ident = input.parse()?;
+        if !input.peek(Token![=]) {
+            return Ok(FieldArg::Name(name));
+        }
+        input.parse::<Token![=]>()?;
+        if input.peek(syn::LitStr) {
+            Ok(FieldArg::Inline(name, input.parse()?))
+        } else {
+            Ok(FieldArg::Expr(name, Box::new(input.parse()?)))
+        }
+    }
+}
+
+/// The attribute without the capture parts, for darling, and the capture parts.
+pub(crate) fn split(metadata: TokenStream) -> syn::Result<(TokenStream, Capture)> {
+    let mut capture = Capture::default();
+    let mut kept = vec![];
+    for segment in segments(metadata) {
+        let tokens: Vec<TokenTree> = segment.clone().into_iter().collect();
+        match tokens.as_slice() {
+            [TokenTree::Ident(name), TokenTree::Group(group)]
+                if name == "fields" && group.delimiter() == Delimiter::Parenthesis =>
+            {
+                let args =
+                    Punctuated::<FieldArg, Token![,]>::parse_terminated.parse2(group.stream())?;
+                let mut inline = vec![];
+                let mut captured = false;
+                for arg in args {
+                    match arg {
+                        FieldArg::Inline(field, ty) => inline.push(quote! { #field = #ty }),
+                        FieldArg::Name(field) => {
+                            captured = true;
+                            capture.fields.push((field, None));
+                        }
+                        FieldArg::Expr(field, value) => {
+                            captured = true;
+                            capture.fields.push((field, Some(*value)));
+                        }
+                    }
+                }
+                // `fields()` is an inline definition without fields
+                if !inline.is_empty() || !captured {
+                    kept.push(quote! { #name(#(#inline),*) });
+                }
+            }
+            [TokenTree::Ident(name), TokenTree::Group(group)]
+                if name == "skip" && group.delimiter() == Delimiter::Parenthesis =>
+            {
+                capture.skip.extend(
+                    Punctuated::<syn::Ident, Token![,]>::parse_terminated.parse2(group.stream())?,
+                );
+            }
+            [TokenTree::Ident(name), TokenTree::Punct(eq), value]
+                if name == "args" && eq.as_char() == '=' =>
+            {
+                let value = value.to_string();
+                if value != "all" && value != "\"all\"" {
+                    return Err(syn::Error::new_spanned(
+                        segment,
+                        "args only takes `all`, name the arguments to record in fields(...)",
+                    ));
+                }
+                capture.all = true;
+            }
+            _ => kept.push(segment),
+        }
+    }
+    Ok((quote! { #(#kept),* }, capture))
+}
+
+/// `metadata` split at its top level commas.
+fn segments(metadata: TokenStream) -> Vec<TokenStream> {
+    let mut segments = vec![];
+    let mut segment = TokenStream::new();
+    for token in metadata {
+        match token {
+            TokenTree::Punct(p) if p.as_char() == ',' => {
+                segments.push(std::mem::take(&mut segment));
+            }
+            token => segment.extend(Some(token)),
+        }
+    }
+    if !segment.is_empty() {
+        segments.push(segment);
+    }
+    segments
+}
+
+impl Capture {
+    /// Field and value of everything to record, for the function `sig` of the event `table`.
+    pub(crate) fn fields(
+        &self,
+        sig: &syn::Signature,
+        table: &str,
+        event: &crate::Event,
+    ) -> syn::Result<Vec<(syn::LitStr, syn::Expr)>> {
+        let args: Vec<&syn::Ident> = sig
+            .inputs
+            .iter()
+            .filter_map(|arg| match arg {
+                syn::FnArg::Typed(arg) => match arg.pat.as_ref() {
+                    syn::Pat::Ident(pat) => Some(&pat.ident),
+                    _ => None,
+                },
+                syn::FnArg::Receiver(_) => None,
+            })
+            .collect();
+
+        let mut errors: Option<syn::Error> = None;
+        let mut error = |error: syn::Error| match errors.as_mut() {
+            Some(errors) => errors.combine(error),
+            None => errors = Some(error),
+        };
+        let named = self
+            .fields
+            .iter()
+            .filter(|(_, value)| value.is_none())
+            .map(|(name, _)| (name, true));
+        let skipped = self.skip.iter().map(|name| (name, false));
+        for (name, recorded) in named.chain(skipped) {
+            if args.contains(&name) {
+                continue;
+            }
+            let suggestion =
+                crate::did_you_mean(&name.to_string(), args.iter().map(|a| a.to_string()));
+            let hint = if suggestion.is_empty() && recorded {
+                format!(", record other values with fields({} = <expr>)", name)
+            } else {
+                suggestion
+            };
+            error(syn::Error::new(
+                name.span(),
+                format!("No argument named \"{}\"{}", name, hint),
+            ));
+        }
+
+        let mut fields: Vec<(syn::LitStr, syn::Expr)> = self
+            .fields
+            .iter()
+            .map(|(name, value)| {
+                let value = value.clone().unwrap_or_else(|| parse_quote!(#name));
+                (syn::LitStr::new(&name.to_string(), name.span()), value)
+            })
+            .collect();
+        if self.all {
+            for arg in args {
+                let field = arg.to_string();
+                if self.skip.contains(arg) || fields.iter().any(|(f, _)| f.value() == field) {
+                    continue;
+                }
+                if event.untyped || event.fields.contains_key(&field) {
+                    fields.push((syn::LitStr::new(&field, arg.span()), parse_quote!(#arg)));
+                } else {
+                    error(syn::Error::new(
+                        arg.span(),
+                        format!(
+                            "Argument \"{}\" is not a field of the event \"{}\", add it to \
+                             skip(...) or to the fields of the event{}",
+                            field,
+                            table,
+                            crate::did_you_mean(&field, event.fields.keys())
+                        ),
+                    ));
+                }
+            }
+        }
+        match errors {
+            Some(errors) => Err(errors),
+            None => Ok(fields),
+        }
+    }
+}
@@ -0,0 +1,246 @@
+//! Finding and reading the events files.
+//!
+//! `EVENTS_PATH` lists the files to use, separated like `PATH`. Without it the `observer.json`,
+//! `observer.toml`, `observer.yaml` or `observer.yml` files of the directory of the crate being
+//! compiled, or of its nearest parent having one, are used, so every crate of a workspace can
+//! have its own. Relative paths are looked up in the crate directory first. TOML and YAML files
+//! need the `toml` and `yaml` features of observer.
+//!
+//! The files are read by `observer_events`, includes and all. An event included under the
+//! namespace `observer__pg` is named `observer__pg__<event>`, as the functions with
+//! `#[observed(namespace = "observer__pg")]` expect.
+//!
+//! With `OBSERVER_WRITE_EVENTS=1` the events defined inline with their fields and missing from
+//! the files are written to the first JSON file of `EVENTS_PATH`, or else to the `observer.json`
+//! of the crate directory, created if needed. The file is written back pretty printed, with
+//! sorted keys. Crates compiled in parallel take turns through a lock on `<file>.lock`, and the
+//! file is replaced whole, so it is never seen half written.
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const FILE_NAMES: &[&str] = &[
+    "observer.json",
+    "observer.toml",
+    "observer.yaml",
+    "observer.yml",
+];
+
+pub(crate) type Events = HashMap<String, crate::Event>;
+
+type Loaded = Result<Option<Arc<Events>>, String>;
+
+/// `CARGO_MANIFEST_DIR` and `EVENTS_PATH`.
+type Key = (Option<String>, Option<String>);
+
+lazy_static! {
+    /// Loaded once per crate directory and `EVENTS_PATH`, errors are reported at every
+    /// `#[observed]` function.
+    static ref LOADED: Mutex<HashMap<Key, Loaded>> =
+        Mutex::new(HashMap::new());
+}
+
+/// `None` when there is no events file to be found.
+pub(crate) fn get() -> Loaded {
+    let key = (
+        env::var("CARGO_MANIFEST_DIR").ok(),
+        env::var("EVENTS_PATH").ok(),
+    );
+    LOADED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .entry(key)
+        .or_insert_with(|| Ok(load(&paths()?)?.map(Arc::new)))
+        .clone()
+}
+
+fn paths() -> Result<Vec<PathBuf>, String> {
+    let current = env::current_dir().map_err(|e| format!("current_dir not found: {}", e))?;
+    let crate_dir = env::var_os("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| current.clone());
+    if let Some(paths) = env::var_os("EVENTS_PATH") {
+        return Ok(env::split_paths(&paths)
+            .filter(|path| !path.as_os_str().is_empty())
+            .map(|path| {
+                let in_crate = crate_dir.join(&path);
+                if path.is_relative() && in_crate.exists() {
+                    in_crate
+                } else {
+                    path
+                }
+            })
+            .collect());
+    }
+    Ok(find(&crate_dir)
+        .or_else(|| find(&current))
+        .unwrap_or_default())
+}
+
+/// The events files of `dir` or of its nearest parent having some.
+fn find(dir: &Path) -> Option<Vec<PathBuf>> {
+    dir.ancestors().find_map(|dir| {
+        let found: Vec<PathBuf> = FILE_NAMES
+            .iter()
+            .map(|name| dir.join(name))
+            .filter(|path| path.exists())
+            .collect();
+        if found.is_empty() {
+            None
+        } else {
+            Some(found)
+        }
+    })
+}
+
+fn load(paths: &[PathBuf]) -> Result<Option<Events>, String> {
+    if paths.is_empty() {
+        return Ok(None);
+    }
+    observer_events::merge(paths)?
+        .into_iter()
+        .map(|(name, (event, path))| {
+            serde_json::from_value(event)
+                .map(|event| (name.clone(), event))
+                .map_err(|e| format!("Event \"{}\" of {}: {}", name, path.display(), e))
+        })
+        .collect::<Result<Events, String>>()
+        .map(Some)
+}
+
+/// Adds `event` to the events file inline definitions are written to, unless it has an event
+/// `name` already. The events loaded for this compilation are left as they are.
+pub(crate) fn write(name: &str, event: &crate::Event) -> Result<(), String> {
+    let path = match paths()?
+        .into_iter()
+        .find(|path| path.extension().and_then(|e| e.to_str()) == Some("json"))
+    {
+        Some(path) => path,
+        None => env::var_os("CARGO_MANIFEST_DIR")
+            .map(PathBuf::from)
+            .ok_or("CARGO_MANIFEST_DIR not set, not able to find the crate directory")?
+            .join("observer.json"),
+    };
+    write_to(&path, name, event)
+}
+
+fn write_to(path: &Path, name: &str, event: &crate::Event) -> Result<(), String> {
+    let with_suffix = |suffix: &str| {
+        let mut file = path.as_os_str().to_os_string();
+        file.push(suffix);
+        PathBuf::from(file)
+    };
+    let lock_path = with_suffix(".lock");
+    // unlocked when dropped
+    let lock = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .map_err(|e| format!("Not able to open {}: {}", lock_path.display(), e))?;
+    lock.lock()
+        .map_err(|e| format!("Not able to lock {}: {}", lock_path.display(), e))?;
+
+    let mut events = match std::fs::read_to_string(path) {
+        Ok(text) => match observer_events::parse(path, &text)? {
+            serde_json::Value::Object(events) => events,
+            _ => {
+                return Err(format!(
+                    "{} has to map event names to events",
+                    path.display()
+                ))
+            }
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => serde_json::Map::new(),
+        Err(e) => return Err(format!("Not able to load {}: {}", path.display(), e)),
+    };
+    if events.contains_key(name) {
+        return Ok(());
+    }
+    let event = serde_json::to_value(event).map_err(|e| e.to_string())?;
+    events.insert(name.to_string(), event);
+    let text = serde_json::to_string_pretty(&events).map_err(|e| e.to_string())?;
+    let temp = with_suffix(&format!(".{}.tmp", std::process::id()));
+    std::fs::write(&temp, text + "\n")
+        .and_then(|_| std::fs::rename(&temp, path))
+        .map_err(|e| {
+            let _ = std::fs::remove_file(&temp);
+            format!("Not able to write {}: {}", path.display(), e)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    /// A fresh directory with `files` written to it.
+    fn dir(files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "observer-events-{}-{}",
+            std::process::id(),
+            files
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join("-")
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, text) in files {
+            std::fs::write(dir.join(name), text).unwrap();
+        }
+        dir
+    }
+
+    const PAY: &str = r#"{"critical": false, "result_type": "i32", "fields": {"amount": "i32"}}"#;
+
+    #[test]
+    fn inline_events_are_written_once() {
+        let dir = dir(&[("observer.json", &format!(r#"{{"pay": {}}}"#, PAY))]);
+        let path = dir.join("observer.json");
+        let refund: crate::Event = serde_json::from_str(PAY).unwrap();
+        let mut changed = refund.clone();
+        changed.critical = true;
+        super::write_to(&path, "refund", &refund).unwrap();
+        super::write_to(&path, "refund", &changed).unwrap();
+        super::write_to(&path, "pay", &changed).unwrap();
+
+        let events = super::load(&[path]).unwrap().unwrap();
+        assert!(!events["pay"].critical);
+        assert!(!events["refund"].critical);
+        assert_eq!(events["refund"].fields["amount"].ty(), "i32");
+
+        let created = dir.join("new.json");
+        super::write_to(&created, "refund", &refund).unwrap();
+        assert!(super::load(&[created])
+            .unwrap()
+            .unwrap()
+            .contains_key("refund"));
+    }
+
+    #[test]
+    fn parallel_writes_keep_every_event() {
+        let dir = dir(&[("parallel.json", "{}")]);
+        let path = dir.join("parallel.json");
+        let event: crate::Event = serde_json::from_str(PAY).unwrap();
+        let writers: Vec<_> = (0..8)
+            .map(|i| {
+                let (path, event) = (path.clone(), event.clone());
+                std::thread::spawn(move || {
+                    super::write_to(&path, &format!("pay_{}", i), &event).unwrap()
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(super::load(&[path]).unwrap().unwrap().len(), 8);
+        let mut files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["parallel.json", "parallel.json.lock"]);
+    }
+}
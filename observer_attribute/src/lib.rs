@@ -1,3 +1,5 @@
+// `darling(default)` expands to code clippy would write with `unwrap_or_default`
+#![allow(clippy::manual_unwrap_or_default)]
 #[macro_use]
 extern crate syn;
 #[macro_use]
@@ -8,47 +10,104 @@ extern crate lazy_static;
 extern crate serde_derive;
 extern crate proc_macro;
 
+mod balance;
+mod capture;
+mod events;
+mod macros;
+mod resulty;
+mod types;
+
 use darling::FromMeta;
-use std::collections::HashMap;
-use std::str::FromStr;
+use std::collections::{HashMap, HashSet};
+use std::env;
 use std::string::ToString;
-use std::{env, fs::File};
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 struct Event {
     critical: bool,
     result_type: String,
-    fields: HashMap<String, String>,
+    fields: HashMap<String, Field>,
+    /// Fields not declared are recorded as they are instead of being errors, for events not in
+    /// the events file.
+    #[serde(skip)]
+    untyped: bool,
 }
 
-fn get_path() -> String {
-    env::var("EVENTS_PATH").unwrap_or_else(|_| {
-        let mut current = std::env::current_dir().expect("current_dir not found");
-        loop {
-            let path = current.join("observer.json");
-            if path.exists() {
-                return path.to_string_lossy().to_string();
-            }
-            current = match current.parent() {
-                Some(p) => p.to_owned(),
-                None => panic!("Could not find observer.json"),
-            };
+/// Either the type, e.g. `"i32"`, or `{"type": "i32", "required": true}` (or `"optional"`).
+/// Required fields the function never observes are errors, unmarked ones warnings.
+///
+/// Objects and enums give their `fields` and `values` the same way, see `types`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+enum Field {
+    Type(String),
+    Marked {
+        #[serde(rename = "type")]
+        ty: String,
+        #[serde(default)]
+        required: bool,
+        #[serde(default)]
+        optional: bool,
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        fields: HashMap<String, Field>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        values: Vec<String>,
+    },
+}
+
+impl Field {
+    fn ty(&self) -> &str {
+        match self {
+            Field::Type(ty) | Field::Marked { ty, .. } => ty,
         }
-    })
+    }
+
+    fn fields(&self) -> Option<&HashMap<String, Field>> {
+        match self {
+            Field::Type(_) => None,
+            Field::Marked { fields, .. } => Some(fields),
+        }
+    }
+
+    fn values(&self) -> &[String] {
+        match self {
+            Field::Type(_) => &[],
+            Field::Marked { values, .. } => values,
+        }
+    }
 }
 
-lazy_static! {
-    static ref EVENTS: HashMap<String, Event> = {
-        let events_path = get_path();
+/// `OBSERVER_LENIENT=1`, e.g. in the `[env]` of `.cargo/config.toml`, makes every
+/// `#[observed]` function lenient.
+fn lenient_crate() -> bool {
+    matches!(
+        env::var("OBSERVER_LENIENT").as_deref(),
+        Ok("1") | Ok("true")
+    )
+}
 
-        println!("Events Path:: {}", events_path);
-        let events_file =
-            File::open(&events_path).expect(&format!("Not able to load {}", events_path));
-        serde_json::from_reader(events_file).expect(&format!("Json parse error {}", events_path))
-    };
+/// `OBSERVER_WRITE_EVENTS=1` adds the events defined inline and missing from the events files
+/// to them, see `events::write`.
+fn write_events() -> bool {
+    matches!(
+        env::var("OBSERVER_WRITE_EVENTS").as_deref(),
+        Ok("1") | Ok("true")
+    )
 }
 
-const WHITELIST_EVENTS: &'static [&'static str] = &[
+/// Field types with an `observe_<type>` function in `observer::observe_fields`.
+/// `value` is anything serializable, recorded as it is.
+const FIELD_TYPES: &[&str] = &[
+    "string", "bool", "char", "i8", "i16", "i32", "i64", "isize", "u8", "u16", "u32", "u64",
+    "usize", "f64", "f32", "value",
+];
+
+/// Result types with an `observe_result_<type>` function, the field types and these.
+const RESULT_TYPES: &[&str] = &["object", "list", "map"];
+
+const WHITELIST_EVENTS: &[&str] = &[
     "query_by_index",
     "establish",
     "execute",
@@ -56,7 +115,7 @@ const WHITELIST_EVENTS: &'static [&'static str] = &[
     "execute_returning_count",
 ];
 
-const WHITELIST_NAMESPACES: &'static [&'static str] = &["observer__pg", "observer__mysql"];
+const WHITELIST_NAMESPACES: &[&str] = &["observer__pg", "observer__mysql"];
 
 #[derive(Debug, FromMeta)]
 struct MacroArgs {
@@ -64,6 +123,24 @@ struct MacroArgs {
     with_result: bool,
     #[darling(default)]
     namespace: Option<String>,
+    /// Also check that branches observe the same fields, like `#[balanced_if]`.
+    #[darling(default)]
+    balanced: bool,
+    /// Make every field of the event the function never observes an error, not only the ones
+    /// marked `required`. Fields marked `optional` are still allowed.
+    #[darling(default)]
+    complete: bool,
+    /// Accept an event missing from the events file, or no events file at all, recording its
+    /// fields and result untyped.
+    #[darling(default)]
+    lenient: bool,
+    // inline definition of the event, checked against the events file if it is there too
+    #[darling(default)]
+    critical: Option<bool>,
+    #[darling(default)]
+    result_type: Option<String>,
+    #[darling(default)]
+    fields: Option<HashMap<String, String>>,
     // #[darling(default)]
     // id: Option<String>,
     // #[darling(default)]
@@ -75,7 +152,15 @@ pub fn observed(
     metadata: proc_macro::TokenStream,
     input: proc_macro::TokenStream,
 ) -> proc_macro::TokenStream {
-    let attr_args = parse_macro_input!(metadata as syn::AttributeArgs);
+    let (metadata, capture) = match capture::split(metadata.into()) {
+        Ok(split) => split,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let attr_args: syn::AttributeArgs =
+        match Punctuated::<syn::NestedMeta, Token![,]>::parse_terminated.parse2(metadata) {
+            Ok(args) => args.into_iter().collect(),
+            Err(e) => return e.to_compile_error().into(),
+        };
     let args: MacroArgs = match MacroArgs::from_list(&attr_args) {
         Ok(v) => v,
         Err(e) => {
@@ -84,6 +169,7 @@ pub fn observed(
     };
 
     let input_fn: syn::ItemFn = parse_macro_input!(input as syn::ItemFn);
+    let original = input_fn.clone();
     let visibility = input_fn.vis;
     let ident = input_fn.sig.ident;
     let inputs = input_fn.sig.inputs;
@@ -94,22 +180,65 @@ pub fn observed(
     let is_whitelist_event = WHITELIST_EVENTS.contains(&ident.to_string().as_str());
     let is_whitelist_namespace =
         WHITELIST_NAMESPACES.contains(&args.namespace.as_ref().unwrap_or(&"".to_string()).as_str());
-    let table_name = if let Some(name_space) = args.namespace {
-        name_space + "__" + &ident.to_string()
+    let table_name = if let Some(name_space) = &args.namespace {
+        name_space.to_string() + "__" + &ident.to_string()
     } else {
         ident.to_string()
     };
-    let (block, is_critical) = if is_whitelist_event && is_whitelist_namespace {
-        (block, false)
+    let whitelisted = is_whitelist_event && is_whitelist_namespace;
+    // the result is recorded by the function, else the summary of what it returns is
+    let summarized = args.with_result && !whitelisted && !observes_result(&block);
+    let (block, is_critical, warnings) = if whitelisted {
+        (block, false, quote! {})
     } else {
-        (
-            rewrite_func_block(block, &table_name),
-            get_event(&table_name).critical,
-        )
+        let unbalanced = if args.balanced {
+            balance::check_block(&block)
+        } else {
+            None
+        };
+        let rewritten = find_event(&table_name, &args, ident.span()).and_then(|event| {
+            let captured = capture.fields(&original.sig, &table_name, &event)?;
+            let (block, warnings) = rewrite_func_block(
+                block,
+                &table_name,
+                &event,
+                &captured,
+                args.complete,
+                ident.span(),
+            )?;
+            Ok((block, event.critical, warnings))
+        });
+        let rewritten = match (rewritten, unbalanced) {
+            (Ok(_), Some(unbalanced)) => Err(unbalanced),
+            (Err(mut e), Some(unbalanced)) => {
+                e.combine(unbalanced);
+                Err(e)
+            }
+            (rewritten, None) => rewritten,
+        };
+        match rewritten {
+            Ok(rewritten) => rewritten,
+            // the function is kept as written so only the schema errors are reported
+            Err(e) => {
+                let error = e.to_compile_error();
+                return (quote! { #error #original }).into();
+            }
+        }
     };
-    if args.with_result {
+    if summarized {
         (quote! {
         #visibility fn #ident #generics (#inputs) #output #where_clause {
+            #warnings
+            Observe::observe_with_summary(#table_name, #is_critical, || {
+                #block
+            }, |__ok| (&Summarized(__ok)).summarize())
+        }
+        })
+        .into()
+    } else if args.with_result {
+        (quote! {
+        #visibility fn #ident #generics (#inputs) #output #where_clause {
+            #warnings
             Observe::observe_with_result(#table_name, #is_critical, || {
                 #block
             })
@@ -119,6 +248,7 @@ pub fn observed(
     } else {
         (quote! {
         #visibility fn #ident #generics (#inputs) #output #where_clause {
+            #warnings
             Observe::observe_all(#table_name, #is_critical, || {
                 #block
             })
@@ -128,77 +258,256 @@ pub fn observed(
     }
 }
 
-fn rewrite_func_block(mut block: Box<syn::Block>, table_name: &str) -> Box<syn::Block> {
-    let mut stmts: Vec<syn::Stmt> = Vec::new();
-
-    for st in block.stmts.into_iter() {
-        match st {
-            syn::Stmt::Semi(e, s) => match e {
-                //                syn::Expr::Macro(m) => {
-                //                    let mut new_macro = m.clone();
-                //                    if m.mac.path.segments[0].ident.to_string().eq("observe_result") {
-                //                        new_macro.mac.path.segments[0].ident =
-                //                            syn::Ident::new("observe_result_i32", Span::call_site());
-                //                    }
-                //                    // stmts.push(syn::Stmt::Semi(syn::Expr::Macro(new_macro), s));
-                //
-                //                }
-                syn::Expr::Call(c) => {
-                    let call = c.clone();
-                    let args = call.args.clone();
-                    match *c.func {
-                        syn::Expr::Path(p) => {
-                            let mut path = p.clone();
-                            if p.path.segments[0].ident.to_string().eq("observe_field") {
-                                if let syn::Expr::Lit(l) = args[0].clone() {
-                                    if let syn::Lit::Str(s) = l.lit.clone() {
-                                        let func = "observe_".to_string()
-                                            + &get_func(s.value(), table_name);
-                                        path.path.segments[0].ident =
-                                            syn::Ident::new(&func, proc_macro2::Span::call_site());
-                                    }
-                                }
-                            }
-
-                            if p.path.segments[0].ident.to_string().eq("observe_result") {
-                                let f_name =
-                                    "observe_result_".to_string() + &get_result_type(table_name);
-                                path.path.segments[0].ident =
-                                    syn::Ident::new(&f_name, proc_macro2::Span::call_site());
-                            }
-
-                            stmts.push(syn::Stmt::Semi(
-                                syn::Expr::Call(syn::ExprCall {
-                                    attrs: call.attrs,
-                                    func: Box::new(syn::Expr::Path(syn::ExprPath {
-                                        attrs: vec![],
-                                        qself: None,
-                                        path: path.path,
-                                    })),
-                                    paren_token: call.paren_token,
-                                    args: call.args,
-                                }),
-                                s,
-                            ));
-                        }
-                        t => stmts.push(syn::Stmt::Semi(
-                            syn::Expr::Call(syn::ExprCall {
-                                attrs: call.attrs,
-                                func: Box::new(t),
-                                paren_token: call.paren_token,
-                                args: call.args,
-                            }),
-                            s,
-                        )),
-                    }
+/// The rewritten block, starting with recording the `captured` arguments, and warnings for
+/// unmarked fields of the event it never observes, errors if `complete`.
+fn rewrite_func_block(
+    mut block: Box<syn::Block>,
+    table_name: &str,
+    event: &Event,
+    captured: &[(syn::LitStr, syn::Expr)],
+    complete: bool,
+    span: proc_macro2::Span,
+) -> syn::Result<(Box<syn::Block>, proc_macro2::TokenStream)> {
+    let mut rewriter = Rewriter {
+        table_name,
+        event,
+        observed: HashSet::new(),
+        errors: None,
+    };
+    syn::visit_mut::VisitMut::visit_block_mut(&mut rewriter, &mut block);
+    let mut stmts = vec![];
+    for (key, value) in captured {
+        match rewriter.capture(key, value) {
+            Ok(stmt) => stmts.push(stmt),
+            Err(e) => rewriter.error(e),
+        }
+    }
+    block.stmts.splice(0..0, stmts);
+    let mut unobserved: Vec<_> = event
+        .fields
+        .iter()
+        .filter(|(name, _)| !rewriter.observed.contains(*name))
+        .collect();
+    unobserved.sort_by(|a, b| a.0.cmp(b.0));
+    let mut warnings = quote! {};
+    for (name, field) in unobserved {
+        let message = format!(
+            "field \"{}\" of the event \"{}\" is never observed",
+            name, table_name
+        );
+        match field {
+            Field::Marked { required: true, .. } => {
+                rewriter.error(syn::Error::new(span, message + ", it is marked required"))
+            }
+            Field::Marked { optional: true, .. } => {}
+            _ if complete => rewriter.error(syn::Error::new(
+                span,
+                message + ", observe it or mark it \"optional\" in the events file",
+            )),
+            _ => warnings.extend(warning(
+                span,
+                &(message + ", mark it \"optional\" in the events file if that is expected"),
+            )),
+        }
+    }
+    match rewriter.errors {
+        Some(errors) => Err(errors),
+        None => Ok((block, warnings)),
+    }
+}
+
+/// Stable proc macros can not emit warnings, using a deprecated item makes rustc emit one. It is
+/// a `deprecated` lint, so `#![deny(warnings)]` makes it an error and `#[allow(deprecated)]` on
+/// the function silences it; `complete` gives a plain error instead.
+fn warning(span: proc_macro2::Span, message: &str) -> proc_macro2::TokenStream {
+    quote_spanned! {span=>
+        const _: () = {
+            #[deprecated(note = #message)]
+            struct ObserverWarning;
+            let _ = ObserverWarning;
+        };
+    }
+}
+
+/// Rewrites `observe_field("key", v)` to the typed `observe_<type>("key", v)` of the field, and
+/// `observe_result(v)` to `observe_result_<type>(v)`, wherever they appear in the function:
+/// nested blocks, branches, loops, closures, trailing expressions and the arguments of the
+/// macros in `macros`. Items defined inside the function are left alone, they are not part of
+/// its span.
+///
+/// Every call the schema does not allow is reported, not only the first.
+struct Rewriter<'a> {
+    table_name: &'a str,
+    event: &'a Event,
+    /// Keys of every `observe_field` call.
+    observed: HashSet<String>,
+    errors: Option<syn::Error>,
+}
+
+impl Rewriter<'_> {
+    fn error(&mut self, error: syn::Error) {
+        match self.errors.as_mut() {
+            Some(errors) => errors.combine(error),
+            None => self.errors = Some(error),
+        }
+    }
+
+    /// Records `value` as the field `key`, by reference, so arguments are not moved. Values of
+    /// fields an untyped event does not declare are serialized, or else debug formatted.
+    fn capture(&mut self, key: &syn::LitStr, value: &syn::Expr) -> syn::Result<syn::Stmt> {
+        self.observed.insert(key.value());
+        let span = syn::spanned::Spanned::span(value);
+        let encoded = if self.event.untyped && !self.event.fields.contains_key(&key.value()) {
+            quote_spanned! {span=> (&Captured(__observed)).captured() }
+        } else {
+            let ty = get_field_type(key, self.table_name, self.event)?;
+            types::encode(&ty, quote_spanned! {span=> __observed }, &key.value(), span)
+        };
+        syn::parse2(quote_spanned! {span=>
+            observe_typed(#key, { let __observed = &(#value); #encoded });
+        })
+    }
+
+    /// What replaces `call`, if it is an `observe_field` or `observe_result` call.
+    fn rewrite(&mut self, call: &syn::ExprCall) -> syn::Result<Option<syn::Expr>> {
+        let ident = match call.func.as_ref() {
+            syn::Expr::Path(p) if p.qself.is_none() && p.path.segments.len() == 1 => {
+                &p.path.segments[0].ident
+            }
+            _ => return Ok(None),
+        };
+        let args = &call.args;
+        let (func, ty) = if ident == "observe_field" {
+            let key =
+                match args.first() {
+                    Some(syn::Expr::Lit(syn::ExprLit {
+                        lit: syn::Lit::Str(s),
+                        ..
+                    })) => s,
+                    Some(key) => return Err(syn::Error::new_spanned(
+                        key,
+                        "observe_field key must be a string literal, so it can be checked against \
+                         the fields of the event",
+                    )),
+                    None => return Ok(None),
+                };
+            self.observed.insert(key.value());
+            let ty = get_field_type(key, self.table_name, self.event)?;
+            if let (types::Ty::Enum(values), Some(value)) = (&ty, args.iter().nth(1)) {
+                check_enum_literal(key, values, value)?;
+            }
+            match ty {
+                types::Ty::Primitive(p) => (format!("observe_{}", p), None),
+                ty => {
+                    let value = args.iter().nth(1);
+                    let span = value.map_or(ident.span(), syn::spanned::Spanned::span);
+                    let encoded =
+                        types::encode(&ty, quote_spanned! {span=> __observed }, &key.value(), span);
+                    let typed = quote_spanned! {span=>
+                        observe_typed(#key, { let __observed = &(#value); #encoded })
+                    };
+                    return syn::parse2(typed).map(Some);
+                }
+            }
+        } else if ident == "observe_result" {
+            match get_result_type(self.table_name, self.event, ident.span())? {
+                types::Ty::Primitive(p) => (format!("observe_result_{}", p), None),
+                ty => (String::new(), Some(ty)),
+            }
+        } else {
+            return Ok(None);
+        };
+        if let Some(ty) = ty {
+            let value = args.first();
+            let span = value.map_or(ident.span(), syn::spanned::Spanned::span);
+            let encoded = types::encode(&ty, quote_spanned! {span=> __observed }, "result", span);
+            let typed = quote_spanned! {span=>
+                observe_result_typed({ let __observed = &(#value); #encoded })
+            };
+            return syn::parse2(typed).map(Some);
+        }
+        let mut call = call.clone();
+        if let syn::Expr::Path(p) = call.func.as_mut() {
+            let segment = &mut p.path.segments[0];
+            segment.ident = syn::Ident::new(&func, segment.ident.span());
+        }
+        Ok(Some(syn::Expr::Call(call)))
+    }
+}
+
+impl syn::visit_mut::VisitMut for Rewriter<'_> {
+    fn visit_expr_mut(&mut self, expr: &mut syn::Expr) {
+        syn::visit_mut::visit_expr_mut(self, expr);
+        if let syn::Expr::Call(call) = expr {
+            match self.rewrite(call) {
+                Ok(Some(rewritten)) => *expr = rewritten,
+                Ok(None) => {}
+                Err(e) => self.error(e),
+            }
+        }
+    }
+
+    fn visit_macro_mut(&mut self, mac: &mut syn::Macro) {
+        match macros::args(mac) {
+            Some(mut args) => {
+                for arg in args.iter_mut() {
+                    self.visit_expr_mut(arg);
                 }
-                t => stmts.push(syn::Stmt::Semi(t, s)),
-            },
-            t => stmts.push(t),
+                macros::set_args(mac, &args);
+            }
+            None => {
+                if let Some(ident) = macros::observe_ident(mac.tokens.clone()) {
+                    self.error(macros::unchecked(&ident, mac));
+                }
+            }
+        }
+    }
+
+    // statement macros, `println!(..);`, are items to syn
+    fn visit_item_mut(&mut self, item: &mut syn::Item) {
+        if let syn::Item::Macro(item) = item {
+            if item.ident.is_none() {
+                self.visit_macro_mut(&mut item.mac);
+            }
         }
     }
-    block.stmts = stmts;
-    block
+}
+
+/// Whether `block` calls `observe_result`, in macros too, items defined inside it left out.
+fn observes_result(block: &syn::Block) -> bool {
+    struct Finder(bool);
+
+    impl<'ast> syn::visit::Visit<'ast> for Finder {
+        fn visit_expr_call(&mut self, call: &'ast syn::ExprCall) {
+            syn::visit::visit_expr_call(self, call);
+            if let syn::Expr::Path(p) = call.func.as_ref() {
+                self.0 |= p.path.is_ident("observe_result");
+            }
+        }
+
+        fn visit_macro(&mut self, mac: &'ast syn::Macro) {
+            match macros::args(mac) {
+                Some(args) => args.iter().for_each(|arg| self.visit_expr(arg)),
+                // the rewriter reports it, the summary is not wanted on top
+                None => {
+                    self.0 |= matches!(macros::observe_ident(mac.tokens.clone()),
+                        Some(ident) if ident == "observe_result")
+                }
+            }
+        }
+
+        fn visit_item(&mut self, item: &'ast syn::Item) {
+            if let syn::Item::Macro(item) = item {
+                if item.ident.is_none() {
+                    self.visit_macro(&item.mac);
+                }
+            }
+        }
+    }
+
+    let mut finder = Finder(false);
+    syn::visit::Visit::visit_block(&mut finder, block);
+    finder.0
 }
 
 #[proc_macro_attribute]
@@ -206,53 +515,221 @@ pub fn balanced_if(
     _metadata: proc_macro::TokenStream,
     input: proc_macro::TokenStream,
 ) -> proc_macro::TokenStream {
-    let item: syn::Item = syn::parse(input).expect("failed to parse input");
+    let item = parse_macro_input!(input as syn::Item);
 
     // log_simple(&format!("{:#?}", item));
-    check_item(&item);
+    let errors = balance::check_item(&item).map(|e| e.to_compile_error());
 
-    let output = quote! { #item };
+    let output = quote! { #errors #item };
     output.into()
 }
 
-#[proc_macro_derive(Resulty)]
+#[proc_macro_derive(Resulty, attributes(resulty))]
 pub fn derive_resulty(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
-    let item: syn::Item = syn::parse(input.clone()).expect("failed to parse input");
-    let struc = get_struct_name(item).replace("\"", "");
-    let st = &format!("impl Resulty for {} {}", struc, "{}");
-    proc_macro2::TokenStream::from_str(st).unwrap().into()
+    let input = parse_macro_input!(input as syn::DeriveInput);
+    match resulty::derive(&input) {
+        Ok(derived) => derived.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
 }
 
-fn get_struct_name(item: syn::Item) -> String {
-    match item {
-        syn::Item::Struct(struc) => struc.ident.to_string(),
-        _ => panic!("this attribute macro can only apply on structs"),
+/// The event from the events file, checked against the inline definition if there is one,
+/// else the inline definition, else for lenient functions an untyped event.
+fn find_event(table: &str, args: &MacroArgs, span: proc_macro2::Span) -> syn::Result<Event> {
+    let inline = inline_event(args);
+    let events = events::get().map_err(|e| syn::Error::new(span, e))?;
+    let found = events.as_ref().and_then(|events| events.get(table));
+    match (found, inline) {
+        (Some(event), Some(inline)) => {
+            check_inline(table, event, &inline, args, span)?;
+            Ok(event.clone())
+        }
+        (Some(event), None) => Ok(event.clone()),
+        (None, Some(inline)) => {
+            // without `fields(...)` the event records anything, which the file can not say
+            if write_events() && !inline.untyped {
+                events::write(table, &inline).map_err(|e| syn::Error::new(span, e))?;
+            }
+            Ok(inline)
+        }
+        (None, None) if args.lenient || lenient_crate() => Ok(Event {
+            critical: false,
+            result_type: "value".to_string(),
+            fields: HashMap::new(),
+            untyped: true,
+        }),
+        (None, None) => get_event(table, events.as_deref(), span),
     }
 }
 
-fn get_event(table: &str) -> Event {
-    match EVENTS.get(table) {
-        Some(e) => e.clone(),
-        None => panic!("No table named \"{}\" in the events file", table),
+fn inline_event(args: &MacroArgs) -> Option<Event> {
+    if args.critical.is_none() && args.result_type.is_none() && args.fields.is_none() {
+        return None;
     }
+    Some(Event {
+        critical: args.critical.unwrap_or(false),
+        result_type: args
+            .result_type
+            .clone()
+            .unwrap_or_else(|| "value".to_string()),
+        fields: args
+            .fields
+            .iter()
+            .flatten()
+            .map(|(name, ty)| (name.clone(), Field::Type(ty.clone())))
+            .collect(),
+        untyped: args.fields.is_none(),
+    })
 }
 
-fn get_func(field: String, table: &str) -> String {
-    match get_event(table).fields.get(&field) {
-        Some(t) => get_rust_type(t),
-        None => panic!(
-            "No field named \"{}\" in the fields for the table \"{}\"",
-            field, table
+/// The parts given inline have to match the events file.
+fn check_inline(
+    table: &str,
+    event: &Event,
+    inline: &Event,
+    args: &MacroArgs,
+    span: proc_macro2::Span,
+) -> syn::Result<()> {
+    let mut differences = vec![];
+    if args.critical.is_some() && event.critical != inline.critical {
+        differences.push(format!("critical is {}", event.critical));
+    }
+    if args.result_type.is_some()
+        && event.result_type.to_lowercase() != inline.result_type.to_lowercase()
+    {
+        differences.push(format!("result_type is \"{}\"", event.result_type));
+    }
+    if args.fields.is_some() {
+        let mut names: Vec<_> = event.fields.keys().chain(inline.fields.keys()).collect();
+        names.sort();
+        names.dedup();
+        for name in names {
+            match (event.fields.get(name), inline.fields.get(name)) {
+                (Some(a), Some(b)) if a.ty().to_lowercase() == b.ty().to_lowercase() => {}
+                (Some(a), Some(_)) => {
+                    differences.push(format!("field \"{}\" is \"{}\"", name, a.ty()))
+                }
+                (Some(_), None) => differences.push(format!("it has field \"{}\"", name)),
+                (None, _) => differences.push(format!("it has no field \"{}\"", name)),
+            }
+        }
+    }
+    if differences.is_empty() {
+        return Ok(());
+    }
+    Err(syn::Error::new(
+        span,
+        format!(
+            "The inline definition of \"{}\" does not match the events file: {}",
+            table,
+            differences.join(", ")
         ),
+    ))
+}
+
+fn get_event(
+    table: &str,
+    events: Option<&events::Events>,
+    span: proc_macro2::Span,
+) -> syn::Result<Event> {
+    let events = match events {
+        Some(events) => events,
+        None => {
+            return Err(syn::Error::new(
+                span,
+                "Could not find observer.json (or .toml, .yaml) in the crate directory or its \
+                 parents, set EVENTS_PATH to use other files, or make the function lenient",
+            ))
+        }
+    };
+    match events.get(table) {
+        Some(e) => Ok(e.clone()),
+        None => Err(syn::Error::new(
+            span,
+            format!(
+                "No event named \"{}\" in the events file{}, define it there or inline, or \
+                 make the function lenient",
+                table,
+                did_you_mean(table, events.keys())
+            ),
+        )),
+    }
+}
+
+fn get_field_type(field: &syn::LitStr, table: &str, event: &Event) -> syn::Result<types::Ty> {
+    match event.fields.get(&field.value()) {
+        None if event.untyped => Ok(types::Ty::Primitive("value".to_string())),
+        Some(t) => types::parse(t.ty(), Some(t)).map_err(|e| {
+            syn::Error::new(
+                field.span(),
+                format!(
+                    "Field \"{}\" of the event \"{}\": {}",
+                    field.value(),
+                    table,
+                    e
+                ),
+            )
+        }),
+        None => Err(syn::Error::new(
+            field.span(),
+            format!(
+                "No field named \"{}\" in the fields for the event \"{}\"{}",
+                field.value(),
+                table,
+                did_you_mean(&field.value(), event.fields.keys())
+            ),
+        )),
+    }
+}
+
+/// A string literal for an enum field has to be one of its values.
+fn check_enum_literal(key: &syn::LitStr, values: &[String], value: &syn::Expr) -> syn::Result<()> {
+    if let syn::Expr::Lit(syn::ExprLit {
+        lit: syn::Lit::Str(s),
+        ..
+    }) = value
+    {
+        if !values.contains(&s.value()) {
+            return Err(syn::Error::new(
+                s.span(),
+                format!(
+                    "\"{}\" is not a value of the field \"{}\", expected one of {:?}{}",
+                    s.value(),
+                    key.value(),
+                    values,
+                    did_you_mean(&s.value(), values.iter())
+                ),
+            ));
+        }
     }
+    Ok(())
 }
 
-fn get_result_type(table: &str) -> String {
-    get_event(table).result_type.to_lowercase()
+/// `object`, `list` and `map` results keep their own `observe_result_<type>` functions.
+fn get_result_type(table: &str, event: &Event, span: proc_macro2::Span) -> syn::Result<types::Ty> {
+    let result_type = event.result_type.to_lowercase();
+    if RESULT_TYPES.contains(&result_type.as_str()) {
+        return Ok(types::Ty::Primitive(result_type));
+    }
+    types::parse(&event.result_type, None).map_err(|e| {
+        syn::Error::new(
+            span,
+            format!("result_type of the event \"{}\": {}", table, e),
+        )
+    })
 }
 
-fn get_rust_type(storage_type: &str) -> String {
-    storage_type.to_lowercase()
+/// `, did you mean "<name>"?` for the name closest to `name`, if one is close enough.
+fn did_you_mean<S: AsRef<str>>(name: &str, names: impl Iterator<Item = S>) -> String {
+    names
+        .map(|candidate| {
+            let score = strsim::jaro_winkler(name, candidate.as_ref());
+            (score, candidate.as_ref().to_string())
+        })
+        .filter(|(score, _)| *score > 0.8)
+        .max_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(_, candidate)| format!(", did you mean \"{}\"?", candidate))
+        .unwrap_or_default()
 }
 
 //fn log_simple(msg: &str) {
@@ -271,33 +748,3 @@ fn get_rust_type(storage_type: &str) -> String {
 //    file.write_all(msg.as_bytes()).unwrap();
 //    file.write_all("\n".as_bytes()).unwrap();
 //}
-
-fn check_item(item: &syn::Item) {
-    match item {
-        syn::Item::Fn(func) => {
-            for stmt in func.block.stmts.iter() {
-                match stmt {
-                    syn::Stmt::Local(local) => match &local.init {
-                        Some((_, init)) => check_expr(init),
-                        None => {}
-                    },
-                    syn::Stmt::Item(i) => check_item(i),
-                    syn::Stmt::Expr(e) => check_expr(e),
-                    syn::Stmt::Semi(e, _) => check_expr(e),
-                }
-            }
-        }
-        _ => {}
-    }
-}
-
-fn check_expr(expr: &syn::Expr) {
-    match expr {
-        syn::Expr::Array(a) => {
-            for e in a.elems.iter() {
-                check_expr(e)
-            }
-        }
-        _ => {}
-    }
-}
@@ -0,0 +1,88 @@
+//! Macro calls in an observed function.
+//!
+//! The arguments of the standard macros taking expressions (`vec!`, `format!`, `println!`,
+//! `assert_eq!` and the like) are parsed, so `observe_field` and `observe_result` calls in them
+//! are checked like any other. Other macros can take anything, `observe_field` and
+//! `observe_result` calls in them can not be checked and are errors.
+use proc_macro2::{Delimiter, TokenStream, TokenTree};
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+
+const EXPRESSION_MACROS: &[&str] = &[
+    "assert",
+    "assert_eq",
+    "assert_ne",
+    "dbg",
+    "debug_assert",
+    "debug_assert_eq",
+    "debug_assert_ne",
+    "eprint",
+    "eprintln",
+    "format",
+    "format_args",
+    "panic",
+    "print",
+    "println",
+    "todo",
+    "unimplemented",
+    "unreachable",
+    "vec",
+    "write",
+    "writeln",
+];
+
+pub(crate) type Args = Punctuated<syn::Expr, Token![,]>;
+
+/// The arguments of `mac`, if it is a macro taking expressions and they parse as such.
+pub(crate) fn args(mac: &syn::Macro) -> Option<Args> {
+    let name = mac.path.get_ident()?.to_string();
+    if !EXPRESSION_MACROS.contains(&name.as_str()) {
+        return None;
+    }
+    Args::parse_terminated.parse2(mac.tokens.clone()).ok()
+}
+
+pub(crate) fn set_args(mac: &mut syn::Macro, args: &Args) {
+    mac.tokens = quote! { #args };
+}
+
+/// The first `observe_field(..)` or `observe_result(..)` call in `tokens`, other `observe_*`
+/// identifiers are left alone.
+pub(crate) fn observe_ident(tokens: TokenStream) -> Option<proc_macro2::Ident> {
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        match token {
+            TokenTree::Ident(ident) if ident == "observe_field" || ident == "observe_result" => {
+                if matches!(tokens.peek(), Some(TokenTree::Group(group))
+                    if group.delimiter() == Delimiter::Parenthesis)
+                {
+                    return Some(ident);
+                }
+            }
+            TokenTree::Group(group) => {
+                if let Some(ident) = observe_ident(group.stream()) {
+                    return Some(ident);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The error for `ident` showing up in the arguments of `mac`, which are not parsed.
+pub(crate) fn unchecked(ident: &proc_macro2::Ident, mac: &syn::Macro) -> syn::Error {
+    let name = mac
+        .path
+        .segments
+        .last()
+        .map(|segment| segment.ident.to_string())
+        .unwrap_or_default();
+    syn::Error::new(
+        ident.span(),
+        format!(
+            "{} can not be checked inside {}!, call it before the macro and pass the value in",
+            ident, name
+        ),
+    )
+}
@@ -0,0 +1,105 @@
+//! `#[derive(Resulty)]`, see `observer::resulty` for the summaries it produces.
+use proc_macro2::TokenStream;
+use syn::spanned::Spanned;
+
+pub(crate) fn derive(input: &syn::DeriveInput) -> syn::Result<TokenStream> {
+    let name = &input.ident;
+    let mut generics = input.generics.clone();
+    for param in generics.type_params_mut() {
+        param
+            .bounds
+            .push(parse_quote!(::observer::resulty::Resulty));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let summary = match &input.data {
+        syn::Data::Struct(data) => fields(name, &data.fields)?,
+        syn::Data::Enum(data) => {
+            let arms = data.variants.iter().map(|variant| {
+                let ident = &variant.ident;
+                let variant_name = ident.to_string();
+                let pattern = match variant.fields {
+                    syn::Fields::Named(_) => quote! { Self::#ident { .. } },
+                    syn::Fields::Unnamed(_) => quote! { Self::#ident(..) },
+                    syn::Fields::Unit => quote! { Self::#ident },
+                };
+                quote! { #pattern => ::observer::resulty::Resulty::summary(#variant_name) }
+            });
+            quote! {
+                match self {
+                    #(#arms,)*
+                }
+            }
+        }
+        syn::Data::Union(_) => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "Resulty can not be derived for unions, implement it by hand",
+            ))
+        }
+    };
+    Ok(quote! {
+        impl #impl_generics ::observer::resulty::Resulty for #name #ty_generics #where_clause {
+            fn summary(&self) -> ::observer::resulty::Summary {
+                #summary
+            }
+        }
+    })
+}
+
+/// The fields marked `#[resulty]`, else the `id` and `*_id` ones, else all of them. A struct
+/// with one unnamed field is summarized as that field.
+fn fields(name: &syn::Ident, fields: &syn::Fields) -> syn::Result<TokenStream> {
+    let mut marked = vec![];
+    for field in fields {
+        for attr in field.attrs.iter().filter(|a| a.path.is_ident("resulty")) {
+            if !attr.tokens.is_empty() {
+                return Err(syn::Error::new(
+                    attr.span(),
+                    "expected #[resulty], it takes no arguments",
+                ));
+            }
+            marked.push(field);
+        }
+    }
+    match fields {
+        syn::Fields::Unit => {
+            let name = name.to_string();
+            Ok(quote! { ::observer::resulty::Resulty::summary(#name) })
+        }
+        syn::Fields::Unnamed(unnamed) => {
+            if unnamed.unnamed.len() == 1 {
+                return Ok(quote! { ::observer::resulty::Resulty::summary(&self.0) });
+            }
+            let selected: Vec<syn::Index> = unnamed
+                .unnamed
+                .iter()
+                .enumerate()
+                .filter(|(_, field)| {
+                    marked.is_empty() || marked.iter().any(|m| std::ptr::eq(*m, *field))
+                })
+                .map(|(i, _)| syn::Index::from(i))
+                .collect();
+            Ok(quote! {
+                ::observer::resulty::Summary::Array(vec![
+                    #(::observer::resulty::Resulty::summary(&self.#selected),)*
+                ])
+            })
+        }
+        syn::Fields::Named(named) => {
+            let is_id = |field: &&syn::Field| matches!(&field.ident, Some(i) if i == "id" || i.to_string().ends_with("_id"));
+            let selected: Vec<&syn::Field> = if !marked.is_empty() {
+                marked
+            } else if named.named.iter().any(|f| is_id(&f)) {
+                named.named.iter().filter(is_id).collect()
+            } else {
+                named.named.iter().collect()
+            };
+            let entries = selected.iter().map(|field| {
+                let ident = &field.ident;
+                let key = ident.as_ref().map(ToString::to_string);
+                quote! { (#key, ::observer::resulty::Resulty::summary(&self.#ident)) }
+            });
+            Ok(quote! { ::observer::resulty::object(vec![#(#entries),*]) })
+        }
+    }
+}
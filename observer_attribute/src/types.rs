@@ -0,0 +1,214 @@
+//! Types of fields and results in the events file.
+//!
+//! Primitives have their own `observe_<type>` function. The other types are turned into JSON by
+//! the `typed_*` functions of `observer::observe_fields`, nested for `Option<T>` and `list<T>`,
+//! so the value is still type checked by the compiler wherever the type names a Rust type.
+pub(crate) enum Ty {
+    Primitive(String),
+    Timestamp,
+    Duration,
+    Uuid,
+    Option(Box<Ty>),
+    List(Box<Ty>),
+    /// JSON schema of the object, in the format of the events file, checked at runtime.
+    Object(String),
+    Enum(Vec<String>),
+}
+
+/// Names of the types other than primitives, for suggestions.
+const TYPES: &[&str] = &[
+    "timestamp",
+    "duration",
+    "uuid",
+    "option<",
+    "list<",
+    "list",
+    "object",
+    "map",
+    "enum",
+];
+
+/// `fields` and `values` belong to the innermost `object` or `enum` of `ty`.
+pub(crate) fn parse(ty: &str, field: Option<&crate::Field>) -> Result<Ty, String> {
+    let normalized: String = ty
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_lowercase();
+    parse_normalized(&normalized, field).map_err(|e| match e {
+        ParseError::Unsupported(name) => format!(
+            "unsupported type \"{}\"{}",
+            name,
+            crate::did_you_mean(&name, crate::FIELD_TYPES.iter().chain(TYPES.iter()))
+        ),
+        ParseError::Invalid(message) => message,
+    })
+}
+
+enum ParseError {
+    Unsupported(String),
+    Invalid(String),
+}
+
+fn parse_normalized(ty: &str, field: Option<&crate::Field>) -> Result<Ty, ParseError> {
+    if let Some(inner) = generic(ty, "option") {
+        return Ok(Ty::Option(Box::new(parse_normalized(inner, field)?)));
+    }
+    if let Some(inner) = generic(ty, "list").or_else(|| generic(ty, "vec")) {
+        return Ok(Ty::List(Box::new(parse_normalized(inner, field)?)));
+    }
+    match ty {
+        "timestamp" => Ok(Ty::Timestamp),
+        "duration" => Ok(Ty::Duration),
+        "uuid" => Ok(Ty::Uuid),
+        "list" => Ok(Ty::List(Box::new(Ty::Primitive("value".to_string())))),
+        "object" | "map" => {
+            let fields = field.and_then(|f| f.fields()).cloned().unwrap_or_default();
+            let schema = serde_json::json!({"type": "object", "fields": fields});
+            Ok(Ty::Object(schema.to_string()))
+        }
+        "enum" => match field.map(|f| f.values()) {
+            Some(values) if !values.is_empty() => Ok(Ty::Enum(values.to_vec())),
+            _ => Err(ParseError::Invalid(
+                "enum without values, give the allowed strings as \"values\"".to_string(),
+            )),
+        },
+        ty if crate::FIELD_TYPES.contains(&ty) => Ok(Ty::Primitive(ty.to_string())),
+        ty => Err(ParseError::Unsupported(ty.to_string())),
+    }
+}
+
+/// `T` of `name<T>`.
+fn generic<'a>(ty: &'a str, name: &str) -> Option<&'a str> {
+    ty.strip_prefix(name)?.strip_prefix('<')?.strip_suffix('>')
+}
+
+/// Expression turning `value`, a reference, into the JSON of a value of type `ty`. `name` is
+/// the field, for runtime validation messages, `span` that of the value, for type errors.
+pub(crate) fn encode(
+    ty: &Ty,
+    value: proc_macro2::TokenStream,
+    name: &str,
+    span: proc_macro2::Span,
+) -> proc_macro2::TokenStream {
+    if let Some(encoder) = encoder(ty, span) {
+        return quote_spanned! {span=> #encoder(#value) };
+    }
+    match ty {
+        Ty::Enum(values) => quote_spanned! {span=> typed_enum(#name, #value, &[#(#values),*]) },
+        Ty::Object(schema) => quote_spanned! {span=>
+            {
+                static __OBSERVED_SCHEMA: ObjectSchema = ObjectSchema::new(#schema);
+                typed_object(#name, #value, &__OBSERVED_SCHEMA)
+            }
+        },
+        Ty::Option(inner) => {
+            let inner = encode(inner, quote_spanned! {span=> __observed }, name, span);
+            quote_spanned! {span=>
+                match #value {
+                    Some(__observed) => #inner,
+                    None => typed_null(),
+                }
+            }
+        }
+        // items passed straight to their encoder, a closure only calling it is a clippy warning
+        // in the user's code
+        Ty::List(inner) => match encoder(inner, span) {
+            Some(encoder) => quote_spanned! {span=> typed_list(#value.iter().map(#encoder)) },
+            None => {
+                let inner = encode(inner, quote_spanned! {span=> __observed }, name, span);
+                quote_spanned! {span=> typed_list(#value.iter().map(|__observed| #inner)) }
+            }
+        },
+        _ => unreachable!("types without an encoder function are handled above"),
+    }
+}
+
+/// The function encoding a value of `ty` on its own, for types needing nothing else.
+fn encoder(ty: &Ty, span: proc_macro2::Span) -> Option<proc_macro2::TokenStream> {
+    match ty {
+        Ty::Primitive(p) if p == "value" => Some(quote_spanned! {span=> typed_primitive }),
+        Ty::Primitive(p) => {
+            let rust_type = if p == "string" {
+                quote_spanned! {span=> str }
+            } else {
+                let ident = syn::Ident::new(p, span);
+                quote_spanned! {span=> #ident }
+            };
+            Some(quote_spanned! {span=> typed_primitive::<#rust_type> })
+        }
+        Ty::Timestamp => Some(quote_spanned! {span=> typed_timestamp }),
+        Ty::Duration => Some(quote_spanned! {span=> typed_duration }),
+        Ty::Uuid => Some(quote_spanned! {span=> typed_uuid }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, parse};
+
+    /// The expression encoding `value` as a field `f` of type `ty`, `field` giving the
+    /// definition of objects and enums.
+    fn encoded(ty: &str, field: Option<serde_json::Value>) -> String {
+        let field: Option<crate::Field> = field.map(|f| serde_json::from_value(f).unwrap());
+        let ty = parse(ty, field.as_ref()).unwrap();
+        encode(&ty, quote!(&value), "f", proc_macro2::Span::call_site()).to_string()
+    }
+
+    #[test]
+    fn options_and_lists_nest_their_item_type() {
+        assert_eq!(
+            encoded("option<uuid>", None),
+            "match & value { Some (__observed) => typed_uuid (__observed) , \
+             None => typed_null () , }"
+        );
+        assert_eq!(
+            encoded("list<timestamp>", None),
+            "typed_list (& value . iter () . map (typed_timestamp))"
+        );
+        assert_eq!(
+            encoded("Vec<Option<i32>>", None),
+            "typed_list (& value . iter () . map (| __observed | match __observed { \
+             Some (__observed) => typed_primitive :: < i32 > (__observed) , \
+             None => typed_null () , }))"
+        );
+    }
+
+    #[test]
+    fn duration_timestamp_and_uuid_have_their_own_encoders() {
+        assert_eq!(encoded("duration", None), "typed_duration (& value)");
+        assert_eq!(encoded("timestamp", None), "typed_timestamp (& value)");
+        assert_eq!(encoded("uuid", None), "typed_uuid (& value)");
+    }
+
+    #[test]
+    fn enums_carry_their_values() {
+        let field = serde_json::json!({"type": "enum", "values": ["gold", "silver"]});
+        assert_eq!(
+            encoded("enum", Some(field)),
+            r#"typed_enum ("f" , & value , & ["gold" , "silver"])"#
+        );
+        assert_eq!(
+            parse("enum", None).err().unwrap(),
+            "enum without values, give the allowed strings as \"values\""
+        );
+    }
+
+    #[test]
+    fn objects_keep_their_schema_in_a_static() {
+        let field = serde_json::json!({"type": "object", "fields": {"city": "string"}});
+        let encoded = encoded("object", Some(field));
+        assert!(
+            encoded.starts_with("{ static __OBSERVED_SCHEMA : ObjectSchema = ObjectSchema :: new"),
+            "{}",
+            encoded
+        );
+        assert!(
+            encoded.ends_with(r#"typed_object ("f" , & value , & __OBSERVED_SCHEMA) }"#),
+            "{}",
+            encoded
+        );
+        assert!(encoded.contains(r#"\"city\":\"string\""#), "{}", encoded);
+    }
+}
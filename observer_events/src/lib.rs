@@ -0,0 +1,255 @@
+//! Reading the events files of observer, for `#[observed]` at compile time and for
+//! `observer::schema` at runtime.
+//!
+//! An events file maps event names to events, as JSON, or as TOML or YAML by the extension with
+//! the `toml` and `yaml` features. A file can include others, relative to it, optionally under a
+//! namespace:
+//!
+//! ```json
+//! {"include": ["common.json", {"path": "pg.toml", "namespace": "observer__pg"}]}
+//! ```
+//!
+//! The events of `pg.toml` are then named `observer__pg__<event>`. An event defined differently
+//! in two files is an error, the same definition in both is not.
+#[macro_use]
+extern crate serde_derive;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Event name to the event, as written, and the file defining it.
+pub type Merged = HashMap<String, (serde_json::Value, PathBuf)>;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Include {
+    Path(String),
+    Namespaced { path: String, namespace: String },
+}
+
+/// The events of every file of `paths` and of the files they include.
+pub fn merge(paths: &[PathBuf]) -> Result<Merged, String> {
+    let mut merged = Merged::new();
+    for path in paths {
+        read(path, None, &mut merged, &mut vec![])?;
+    }
+    Ok(merged)
+}
+
+/// Adds the events of `path` and of the files it includes to `merged`. `including` are the
+/// files whose includes are being read, to stop cycles.
+fn read(
+    path: &Path,
+    namespace: Option<&str>,
+    merged: &mut Merged,
+    including: &mut Vec<PathBuf>,
+) -> Result<(), String> {
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    if including.contains(&canonical) {
+        return Err(format!("{} includes itself", path.display()));
+    }
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Not able to load {}: {}", path.display(), e))?;
+    let mut events = match parse(path, &text)? {
+        serde_json::Value::Object(events) => events,
+        serde_json::Value::Null => serde_json::Map::new(),
+        _ => {
+            return Err(format!(
+                "{} has to map event names to events",
+                path.display()
+            ))
+        }
+    };
+
+    if let Some(includes) = events.remove("include") {
+        let includes: Vec<Include> = serde_json::from_value(includes).map_err(|e| {
+            format!(
+                "include of {} has to list paths or {{\"path\", \"namespace\"}}: {}",
+                path.display(),
+                e
+            )
+        })?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        including.push(canonical);
+        for include in includes {
+            let (file, inner) = match include {
+                Include::Path(file) => (file, None),
+                Include::Namespaced { path, namespace } => (path, Some(namespace)),
+            };
+            let namespace = match (namespace, inner) {
+                (Some(outer), Some(inner)) => Some(format!("{}__{}", outer, inner)),
+                (outer, inner) => inner.or_else(|| outer.map(ToString::to_string)),
+            };
+            read(&dir.join(file), namespace.as_deref(), merged, including)?;
+        }
+        including.pop();
+    }
+
+    for (name, event) in events {
+        let name = match namespace {
+            Some(namespace) => format!("{}__{}", namespace, name),
+            None => name,
+        };
+        match merged.get(&name) {
+            Some((defined, other)) if *defined != event => {
+                return Err(format!(
+                    "Event \"{}\" is defined differently in {} and {}",
+                    name,
+                    other.display(),
+                    path.display()
+                ))
+            }
+            Some(_) => {}
+            None => {
+                merged.insert(name, (event, path.to_path_buf()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// JSON, or TOML or YAML by the extension.
+pub fn parse(path: &Path, text: &str) -> Result<serde_json::Value, String> {
+    match path.extension().and_then(|e| e.to_str()) {
+        #[cfg(feature = "toml")]
+        Some("toml") => {
+            toml::from_str(text).map_err(|e| format!("Toml parse error {}: {}", path.display(), e))
+        }
+        #[cfg(not(feature = "toml"))]
+        Some("toml") => Err(format!(
+            "{} needs the toml feature of observer",
+            path.display()
+        )),
+        #[cfg(feature = "yaml")]
+        Some("yaml") | Some("yml") => serde_norway::from_str(text)
+            .map_err(|e| format!("Yaml parse error {}: {}", path.display(), e)),
+        #[cfg(not(feature = "yaml"))]
+        Some("yaml") | Some("yml") => Err(format!(
+            "{} needs the yaml feature of observer",
+            path.display()
+        )),
+        _ => serde_json::from_str(text)
+            .map_err(|e| format!("Json parse error {}: {}", path.display(), e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    /// A fresh directory with `files` written to it.
+    fn dir(files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "observer-events-{}-{}",
+            std::process::id(),
+            files
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join("-")
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, text) in files {
+            std::fs::write(dir.join(name), text).unwrap();
+        }
+        dir
+    }
+
+    const PAY: &str = r#"{"critical": false, "result_type": "i32", "fields": {"amount": "i32"}}"#;
+
+    #[test]
+    fn includes_are_namespaced() {
+        let dir = dir(&[
+            (
+                "main.json",
+                r#"{"include": ["common.json", {"path": "pg.json", "namespace": "observer__pg"}]}"#,
+            ),
+            ("common.json", &format!(r#"{{"pay": {}}}"#, PAY)),
+            (
+                "pg.json",
+                &format!(
+                    r#"{{"select": {}, "include": [{{"path": "inner.json", "namespace": "inner"}}]}}"#,
+                    PAY
+                ),
+            ),
+            ("inner.json", &format!(r#"{{"query": {}}}"#, PAY)),
+        ]);
+        let merged = super::merge(&[dir.join("main.json")]).unwrap();
+        let mut names: Vec<_> = merged.keys().cloned().collect();
+        names.sort();
+        assert_eq!(
+            names,
+            ["observer__pg__inner__query", "observer__pg__select", "pay"]
+        );
+        assert_eq!(merged["observer__pg__select"].1, dir.join("pg.json"));
+    }
+
+    #[cfg(all(feature = "toml", feature = "yaml"))]
+    #[test]
+    fn toml_and_yaml_are_read() {
+        let dir = dir(&[
+            ("all.json", r#"{"include": ["pay.toml", "refund.yaml"]}"#),
+            (
+                "pay.toml",
+                "[pay]\ncritical = false\nresult_type = \"i32\"\nfields = { amount = \"i32\" }\n",
+            ),
+            (
+                "refund.yaml",
+                "refund:\n  critical: true\n  result_type: i32\n  fields:\n    amount: i32\n",
+            ),
+        ]);
+        let merged = super::merge(&[dir.join("all.json")]).unwrap();
+        assert_eq!(
+            merged["pay"].0,
+            serde_json::from_str::<serde_json::Value>(PAY).unwrap()
+        );
+        assert_eq!(merged["refund"].0["critical"], serde_json::json!(true));
+        assert_eq!(merged["refund"].0["fields"]["amount"], "i32");
+    }
+
+    #[test]
+    fn include_cycles_are_errors() {
+        let dir = dir(&[
+            ("a.json", r#"{"include": ["b.json"]}"#),
+            ("b.json", r#"{"include": ["a.json"]}"#),
+        ]);
+        let error = super::merge(&[dir.join("a.json")]).unwrap_err();
+        assert!(error.ends_with("a.json includes itself"), "{}", error);
+    }
+
+    #[test]
+    fn missing_files_are_errors() {
+        let dir = dir(&[("missing.json", r#"{"include": ["gone.json"]}"#)]);
+        let error = super::merge(&[dir.join("missing.json")]).unwrap_err();
+        assert!(error.starts_with("Not able to load "), "{}", error);
+        assert!(error.contains("gone.json"), "{}", error);
+    }
+
+    #[test]
+    fn the_same_definition_twice_is_not_an_error() {
+        let dir = dir(&[
+            ("one.json", &format!(r#"{{"pay": {}}}"#, PAY)),
+            ("two.json", &format!(r#"{{"pay": {}}}"#, PAY)),
+        ]);
+        let merged = super::merge(&[dir.join("one.json"), dir.join("two.json")]).unwrap();
+        assert_eq!(merged.len(), 1);
+    }
+
+    #[test]
+    fn conflicting_definitions_are_errors() {
+        let dir = dir(&[
+            ("first.json", &format!(r#"{{"pay": {}}}"#, PAY)),
+            (
+                "second.json",
+                r#"{"pay": {"critical": true, "result_type": "i32", "fields": {}}}"#,
+            ),
+        ]);
+        let error = super::merge(&[dir.join("first.json"), dir.join("second.json")]).unwrap_err();
+        assert!(
+            error.starts_with("Event \"pay\" is defined differently in"),
+            "{}",
+            error
+        );
+    }
+}